@@ -1,12 +1,60 @@
 use std::path::PathBuf;
 
-use clap::{ArgGroup, ArgMatches, arg, command, value_parser};
+use clap::builder::PossibleValuesParser;
+use clap::{ArgAction, ArgGroup, ArgMatches, Command, arg, command, value_parser};
+use concurrent::{Severity, ValidationErrorKind};
 
 pub(crate) fn cli() -> ArgMatches {
+    build().get_matches()
+}
+
+pub(crate) fn build() -> Command {
+    let warnings = ValidationErrorKind::ALL
+        .into_iter()
+        .filter(|kind| kind.default_severity() == Severity::Warning)
+        .map(|kind| kind.name());
+
     command!()
+        .arg(arg!(--"deny-warnings" "Fail when any warning is reported").global(true))
+        .arg(
+            arg!(--allow <KIND> "Don't report warnings of this kind")
+                .value_parser(PossibleValuesParser::new(warnings))
+                .action(ArgAction::Append)
+                .global(true),
+        )
         .subcommand(
             command!("render")
-                .about("Render graph to specified format")
+                .about("Render graph to specified format, or the one of the output's extension")
+                .args_conflicts_with_subcommands(true)
+                .subcommand_negates_reqs(true)
+                .arg(arg!(-i --input <INPUT> "Raw input (inline)"))
+                .arg(
+                    arg!(-f --file <INPUT> "Source file to process")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .group(
+                    ArgGroup::new("input-source")
+                        .args(["input", "file"])
+                        .required(true)
+                        .multiple(false),
+                )
+                .arg(
+                    arg!(-o --output <OUTPUT> "Output file: .pdf, .svg, .png, .dot, .mmd, .txt or .graph")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true),
+                )
+                .arg(arg!(--"critical-path" "Highlight the critical path"))
+                .arg(
+                    arg!(--scale <FACTOR> "Scale of PNG images")
+                        .value_parser(value_parser!(f32)),
+                )
+                .arg(
+                    arg!(--dpi <DPI> "Resolution of PNG images")
+                        .value_parser(value_parser!(f32))
+                        .conflicts_with("scale"),
+                )
+                .arg(arg!(--ascii "Only use ASCII characters in text"))
+                .arg(arg!(--compact "One line per layer of tasks in text"))
                 .subcommand(
                     command!("pdf")
                         .about("Render to PDF file")
@@ -24,8 +72,120 @@ pub(crate) fn cli() -> ArgMatches {
                         .arg(
                             arg!(-o --output <OUTPUT> "Output to PDF File")
                                 .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(arg!(--"critical-path" "Highlight the critical path")),
+                )
+                .subcommand(
+                    command!("svg")
+                        .about("Render to SVG file")
+                        .arg(arg!(-i --input <INPUT> "Raw input (inline)"))
+                        .arg(
+                            arg!(-f --file <INPUT> "Source file to process")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .group(
+                            ArgGroup::new("input-source")
+                                .args(["input", "file"])
+                                .required(true)
+                                .multiple(false),
+                        )
+                        .arg(
+                            arg!(-o --output <OUTPUT> "Output to SVG File")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(arg!(--"critical-path" "Highlight the critical path")),
+                )
+                .subcommand(
+                    command!("png")
+                        .about("Render to PNG file")
+                        .arg(arg!(-i --input <INPUT> "Raw input (inline)"))
+                        .arg(
+                            arg!(-f --file <INPUT> "Source file to process")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .group(
+                            ArgGroup::new("input-source")
+                                .args(["input", "file"])
+                                .required(true)
+                                .multiple(false),
+                        )
+                        .arg(
+                            arg!(-o --output <OUTPUT> "Output to PNG File")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(arg!(--"critical-path" "Highlight the critical path"))
+                        .arg(
+                            arg!(--scale <FACTOR> "Scale of the image, 1 being 96 DPI")
+                                .value_parser(value_parser!(f32)),
+                        )
+                        .arg(
+                            arg!(--dpi <DPI> "Resolution of the image")
+                                .value_parser(value_parser!(f32))
+                                .conflicts_with("scale"),
                         ),
                 )
+                .subcommand(
+                    command!("dot")
+                        .about("Render to Graphviz DOT")
+                        .arg(arg!(-i --input <INPUT> "Raw input (inline)"))
+                        .arg(
+                            arg!(-f --file <INPUT> "Source file to process")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .group(
+                            ArgGroup::new("input-source")
+                                .args(["input", "file"])
+                                .required(true)
+                                .multiple(false),
+                        )
+                        .arg(
+                            arg!(-o --output <OUTPUT> "Output to DOT file (prints it when omitted)")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(arg!(--"critical-path" "Highlight the critical path")),
+                )
+                .subcommand(
+                    command!("mermaid")
+                        .about("Render to a Mermaid flowchart")
+                        .arg(arg!(-i --input <INPUT> "Raw input (inline)"))
+                        .arg(
+                            arg!(-f --file <INPUT> "Source file to process")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .group(
+                            ArgGroup::new("input-source")
+                                .args(["input", "file"])
+                                .required(true)
+                                .multiple(false),
+                        )
+                        .arg(
+                            arg!(-o --output <OUTPUT> "Output to Mermaid file (prints it when omitted)")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(arg!(--"critical-path" "Highlight the critical path")),
+                )
+                .subcommand(
+                    command!("text")
+                        .about("Draw in the terminal")
+                        .arg(arg!(-i --input <INPUT> "Raw input (inline)"))
+                        .arg(
+                            arg!(-f --file <INPUT> "Source file to process")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .group(
+                            ArgGroup::new("input-source")
+                                .args(["input", "file"])
+                                .required(true)
+                                .multiple(false),
+                        )
+                        .arg(
+                            arg!(-o --output <OUTPUT> "Output to text file (prints it when omitted)")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(arg!(--"critical-path" "Mark the critical tasks with *"))
+                        .arg(arg!(--ascii "Only use ASCII characters"))
+                        .arg(arg!(--compact "One line per layer of tasks")),
+                )
                 .subcommand(
                     command!("ir")
                         .about("Render to IR file")
@@ -63,7 +223,133 @@ pub(crate) fn cli() -> ArgMatches {
                 .arg(
                     arg!(-o --output <OUTPUT> "Output to the converted file")
                         .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(arg!(--fix "Remove redundant dependencies before converting")),
+        )
+        .subcommand(
+            command!("simulate")
+                .about("Execute a Fork/Join program step by step")
+                .arg(arg!(-i --input <INPUT> "Raw input (inline)"))
+                .arg(
+                    arg!(-f --file <INPUT> "Source file to process")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .group(
+                    ArgGroup::new("input-source")
+                        .args(["input", "file"])
+                        .required(true)
+                        .multiple(false),
+                ),
+        )
+        .subcommand(
+            command!("orders")
+                .about("List the valid execution orders of a graph")
+                .arg(arg!(-i --input <INPUT> "Raw input (inline)"))
+                .arg(
+                    arg!(-f --file <INPUT> "Source file to process")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .group(
+                    ArgGroup::new("input-source")
+                        .args(["input", "file"])
+                        .required(true)
+                        .multiple(false),
+                )
+                .arg(arg!(--count "Only print how many orders there are"))
+                .arg(arg!(--limit <N> "Print at most N orders").value_parser(value_parser!(usize)))
+                .arg(
+                    arg!(--random <SEED> "Print a single order chosen uniformly at random")
+                        .value_parser(value_parser!(u64)),
+                )
+                .group(
+                    ArgGroup::new("mode")
+                        .args(["count", "limit", "random"])
+                        .multiple(false),
+                ),
+        )
+        .subcommand(
+            command!("parallelize")
+                .about("Derive the most parallel graph from the tasks' read/write sets")
+                .arg(arg!(-i --input <INPUT> "Raw input (inline)"))
+                .arg(
+                    arg!(-f --file <INPUT> "Source file to process")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .group(
+                    ArgGroup::new("input-source")
+                        .args(["input", "file"])
+                        .required(true)
+                        .multiple(false),
+                )
+                .arg(
+                    arg!(-o --output <OUTPUT> "Output to IR file (prints it when omitted)")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            command!("critical-path")
+                .about("Compute the task schedule and critical path from the durations")
+                .visible_alias("stats")
+                .arg(arg!(-i --input <INPUT> "Raw input (inline)"))
+                .arg(
+                    arg!(-f --file <INPUT> "Source file to process")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .group(
+                    ArgGroup::new("input-source")
+                        .args(["input", "file"])
+                        .required(true)
+                        .multiple(false),
+                ),
+        )
+        .subcommand(
+            command!("schedule")
+                .about("Schedule the tasks on a fixed number of processors")
+                .arg(arg!(-i --input <INPUT> "Raw input (inline)"))
+                .arg(
+                    arg!(-f --file <INPUT> "Source file to process")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .group(
+                    ArgGroup::new("input-source")
+                        .args(["input", "file"])
+                        .required(true)
+                        .multiple(false),
+                )
+                .arg(
+                    arg!(-p --processors <N> "Number of processors")
+                        .value_parser(value_parser!(usize))
+                        .default_value("2"),
+                )
+                .arg(
+                    arg!(--heuristic <NAME> "Which ready task runs first")
+                        .value_parser(["hlfet", "cp", "fifo"])
+                        .default_value("hlfet"),
+                )
+                .arg(
+                    arg!(-o --output <OUTPUT> "Output the Gantt chart to a PDF file")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            command!("lint")
+                .about("Report the warnings about a graph")
+                .arg(arg!(-i --input <INPUT> "Raw input (inline)"))
+                .arg(
+                    arg!(-f --file <INPUT> "Source file to process")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .group(
+                    ArgGroup::new("input-source")
+                        .args(["input", "file"])
+                        .required(true)
+                        .multiple(false),
+                )
+                .arg(arg!(--fix "Print the graph without the redundant dependencies"))
+                .arg(
+                    arg!(-o --output <OUTPUT> "Write the fixed graph to an IR file")
+                        .value_parser(value_parser!(PathBuf))
+                        .requires("fix"),
                 ),
         )
-        .get_matches()
 }
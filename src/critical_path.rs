@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use petgraph::Direction;
+use petgraph::algo::toposort;
+use petgraph::visit::EdgeRef;
+
+use crate::Error;
+use crate::graph::{Graph, Ir, Valid, ir};
+
+/// Duration assumed for the tasks that don't declare one.
+pub const DEFAULT_DURATION: u64 = 1;
+
+/// Schedule of a single task when every task starts as soon as it can.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Activity {
+    pub name: String,
+    pub duration: u64,
+    pub earliest_start: u64,
+    pub earliest_finish: u64,
+    pub latest_start: u64,
+    pub latest_finish: u64,
+    /// How much the task can be delayed without delaying the whole program.
+    pub slack: u64,
+}
+
+impl Activity {
+    pub fn is_critical(&self) -> bool {
+        self.slack == 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CriticalPath {
+    /// Tasks in topological order.
+    pub activities: Vec<Activity>,
+    /// Total duration of the program with unlimited processors.
+    pub makespan: u64,
+    /// One of the longest chains of tasks, from a source to a sink.
+    pub path: Vec<String>,
+    /// Every precedence edge between two critical tasks that leaves no gap.
+    pub edges: Vec<(String, String)>,
+}
+
+impl CriticalPath {
+    pub fn is_critical(&self, task: &str) -> bool {
+        self.activities
+            .iter()
+            .any(|activity| activity.name == task && activity.is_critical())
+    }
+
+    pub fn is_critical_edge(&self, from: &str, to: &str) -> bool {
+        self.edges.iter().any(|(a, b)| a == from && b == to)
+    }
+}
+
+impl Graph<ir::Node, Ir, Valid> {
+    /// Earliest/latest start times, slack and critical path of the tasks, using
+    /// their `:n` durations.
+    ///
+    /// The alternatives of a choice are weighed as if they all ran, so the
+    /// makespan is the one of the slowest way the choices can go.
+    ///
+    /// Fails when a chain of tasks lasts longer than fits in a `u64`.
+    pub fn critical_path(&self) -> Result<CriticalPath, Error> {
+        let mut durations = HashMap::new();
+        collect_durations(&self.0, &mut durations);
+
+        let graph = self.to_petgraph();
+        // Validated graphs are acyclic.
+        let order = toposort(&graph, None).unwrap_or_default();
+        let duration = |idx: petgraph::graph::NodeIndex| {
+            durations
+                .get(graph[idx].as_str())
+                .copied()
+                .unwrap_or(DEFAULT_DURATION)
+        };
+
+        // Every other time is at most the makespan, so only the finish times
+        // can overflow.
+        let mut earliest = vec![0; graph.node_count()];
+        let mut finish = vec![0u64; graph.node_count()];
+        for &idx in &order {
+            let start = graph
+                .neighbors_directed(idx, Direction::Incoming)
+                .map(|pred| finish[pred.index()])
+                .max()
+                .unwrap_or(0);
+            earliest[idx.index()] = start;
+            finish[idx.index()] = start.checked_add(duration(idx)).ok_or_else(|| {
+                Error::Overflow(format!(
+                    "the tasks up to {} last longer than {}",
+                    graph[idx],
+                    u64::MAX
+                ))
+            })?;
+        }
+
+        let makespan = finish.iter().copied().max().unwrap_or(0);
+
+        let mut latest_finish = vec![makespan; graph.node_count()];
+        for &idx in order.iter().rev() {
+            latest_finish[idx.index()] = graph
+                .neighbors_directed(idx, Direction::Outgoing)
+                .map(|succ| latest_finish[succ.index()] - duration(succ))
+                .min()
+                .unwrap_or(makespan);
+        }
+
+        let activities: Vec<_> = order
+            .iter()
+            .map(|&idx| {
+                let i = idx.index();
+                let latest_start = latest_finish[i] - duration(idx);
+                Activity {
+                    name: graph[idx].clone(),
+                    duration: duration(idx),
+                    earliest_start: earliest[i],
+                    earliest_finish: finish[i],
+                    latest_start,
+                    latest_finish: latest_finish[i],
+                    slack: latest_start - earliest[i],
+                }
+            })
+            .collect();
+
+        let critical = |idx: petgraph::graph::NodeIndex| {
+            latest_finish[idx.index()] - duration(idx) == earliest[idx.index()]
+        };
+        let mut edges = vec![];
+        for edge in graph.edge_references() {
+            let (from, to) = (edge.source(), edge.target());
+            let tight = finish[from.index()] == earliest[to.index()];
+            let pair = (graph[from].clone(), graph[to].clone());
+            if critical(from) && critical(to) && tight && !edges.contains(&pair) {
+                edges.push(pair);
+            }
+        }
+
+        // Follow tight edges from a critical source until the program ends.
+        let mut path = vec![];
+        let mut current = order
+            .iter()
+            .copied()
+            .find(|&idx| earliest[idx.index()] == 0 && critical(idx));
+        while let Some(idx) = current {
+            path.push(graph[idx].clone());
+            current = order.iter().copied().find(|&succ| {
+                graph.contains_edge(idx, succ)
+                    && critical(succ)
+                    && finish[idx.index()] == earliest[succ.index()]
+            });
+        }
+
+        Ok(CriticalPath {
+            activities,
+            makespan,
+            path,
+            edges,
+        })
+    }
+}
+
+fn collect_durations<'a>(nodes: &'a [ir::Node], durations: &mut HashMap<&'a str, u64>) {
+    for node in nodes {
+        match node {
+            ir::Node::Atomic(id, _, _, attrs) => {
+                if let Some(duration) = attrs.duration {
+                    durations.insert(id, duration);
+                }
+            }
+            ir::Node::Par(children)
+            | ir::Node::Seq(children)
+            | ir::Node::Loop(children)
+            | ir::Node::Choice(children)
+            | ir::Node::Labelled(_, children, _) => {
+                collect_durations(children, durations);
+            }
+            ir::Node::Dep(_) => {}
+        }
+    }
+}
+
+impl fmt::Display for CriticalPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:>5} {:>5} {:>5} {:>5} {:>5} {:>5}",
+            "task", "dur", "ES", "EF", "LS", "LF", "slack"
+        )?;
+        for activity in &self.activities {
+            writeln!(
+                f,
+                "{:<12} {:>5} {:>5} {:>5} {:>5} {:>5} {:>5}{}",
+                activity.name,
+                activity.duration,
+                activity.earliest_start,
+                activity.earliest_finish,
+                activity.latest_start,
+                activity.latest_finish,
+                activity.slack,
+                if activity.is_critical() { "  *" } else { "" }
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Makespan: {}", self.makespan)?;
+        write!(f, "Critical path: {}", self.path.join(" -> "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn critical_path(input: &str) -> CriticalPath {
+        let graph = ir::parse(input).unwrap();
+        Graph::<ir::Node, Ir, Valid>::new(graph.0)
+            .critical_path()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_longest_branch_is_critical() {
+        let result = critical_path("$s0:2,{[s1:3,s2:4],s3:5},s4:1$");
+
+        assert_eq!(result.makespan, 10);
+        assert_eq!(result.path, vec!["s0", "s1", "s2", "s4"]);
+        let s3 = result.activities.iter().find(|a| a.name == "s3").unwrap();
+        assert_eq!((s3.earliest_start, s3.latest_start, s3.slack), (2, 4, 2));
+        assert!(!result.is_critical_edge("s0", "s3"));
+    }
+
+    #[test]
+    fn test_default_duration() {
+        let result = critical_path("$s0,{s1,s2:3},s3$");
+
+        assert_eq!(result.makespan, 5);
+        assert_eq!(result.path, vec!["s0", "s2", "s3"]);
+    }
+
+    #[test]
+    fn test_overflowing_durations() {
+        let graph = ir::parse("$s0:18446744073709551615,s1:5$").unwrap();
+        let graph = Graph::<ir::Node, Ir, Valid>::new(graph.0);
+
+        assert!(matches!(graph.critical_path(), Err(Error::Overflow(_))));
+    }
+}
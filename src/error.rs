@@ -1,29 +1,182 @@
+use std::fmt;
+use std::ops::Range;
+
+use miette::{Diagnostic, LabeledSpan};
 use thiserror::Error;
 
+/// Byte range of the source a diagnostic points at.
+pub type Span = Range<usize>;
+
 #[derive(Debug, Clone)]
 pub struct ValidationError {
     pub kind: ValidationErrorKind,
+    pub severity: Severity,
     pub message: String,
+    /// Places in the source involved in the error, with what each one is.
+    pub labels: Vec<(Span, String)>,
+    /// How to fix it, when there's an obvious way.
+    pub help: Option<String>,
+}
+
+/// Whether a diagnostic stops the program from being processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    /// Worth fixing, but the program is still valid.
+    Warning,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ValidationErrorKind {
     CircularDependency,
     MissingDependency,
     MissingLabel,
     UnusedLabel,
     UnsupportedDependencies,
+    BernsteinViolation,
+    DuplicateTask,
+    DuplicateLabel,
+    RedundantDependency,
+    UnreachableStatement,
+    DanglingBranch,
+    GotoIntoBranch,
+    UnsupportedLoop,
+    UnsupportedChoice,
+}
+
+impl ValidationErrorKind {
+    pub const ALL: [ValidationErrorKind; 14] = [
+        ValidationErrorKind::CircularDependency,
+        ValidationErrorKind::MissingDependency,
+        ValidationErrorKind::MissingLabel,
+        ValidationErrorKind::UnusedLabel,
+        ValidationErrorKind::UnsupportedDependencies,
+        ValidationErrorKind::BernsteinViolation,
+        ValidationErrorKind::DuplicateTask,
+        ValidationErrorKind::DuplicateLabel,
+        ValidationErrorKind::RedundantDependency,
+        ValidationErrorKind::UnreachableStatement,
+        ValidationErrorKind::DanglingBranch,
+        ValidationErrorKind::GotoIntoBranch,
+        ValidationErrorKind::UnsupportedLoop,
+        ValidationErrorKind::UnsupportedChoice,
+    ];
+
+    /// Name used in diagnostic codes and accepted by `--allow`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValidationErrorKind::CircularDependency => "circular-dependency",
+            ValidationErrorKind::MissingDependency => "missing-dependency",
+            ValidationErrorKind::MissingLabel => "missing-label",
+            ValidationErrorKind::UnusedLabel => "unused-label",
+            ValidationErrorKind::UnsupportedDependencies => "unsupported-dependencies",
+            ValidationErrorKind::BernsteinViolation => "bernstein-violation",
+            ValidationErrorKind::DuplicateTask => "duplicate-task",
+            ValidationErrorKind::DuplicateLabel => "duplicate-label",
+            ValidationErrorKind::RedundantDependency => "redundant-dependency",
+            ValidationErrorKind::UnreachableStatement => "unreachable-statement",
+            ValidationErrorKind::DanglingBranch => "dangling-branch",
+            ValidationErrorKind::GotoIntoBranch => "goto-into-branch",
+            ValidationErrorKind::UnsupportedLoop => "unsupported-loop",
+            ValidationErrorKind::UnsupportedChoice => "unsupported-choice",
+        }
+    }
+
+    pub fn default_severity(&self) -> Severity {
+        match self {
+            ValidationErrorKind::UnusedLabel
+            | ValidationErrorKind::RedundantDependency
+            | ValidationErrorKind::UnreachableStatement => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl TryFrom<&str> for ValidationErrorKind {
+    type Error = Error;
+
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        ValidationErrorKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .ok_or(Error::InvalidParams)
+    }
+}
+
+impl fmt::Display for ValidationErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
 }
 
 impl ValidationError {
     pub fn new(kind: ValidationErrorKind, message: String) -> Self {
-        Self { kind, message }
+        Self {
+            kind,
+            severity: kind.default_severity(),
+            message,
+            labels: vec![],
+            help: None,
+        }
+    }
+
+    /// Points the error at `span`, if the node came from source code.
+    pub fn with_label(mut self, span: Option<Span>, label: impl Into<String>) -> Self {
+        if let Some(span) = span {
+            self.labels.push((span, label.into()));
+        }
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn is_warning(&self) -> bool {
+        self.severity == Severity::Warning
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl Diagnostic for ValidationError {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        Some(Box::new(self.kind))
+    }
+
+    fn severity(&self) -> Option<miette::Severity> {
+        match self.severity {
+            Severity::Error => None,
+            Severity::Warning => Some(miette::Severity::Warning),
+        }
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.help
+            .as_ref()
+            .map(|help| Box::new(help) as Box<dyn fmt::Display>)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        if self.labels.is_empty() {
+            return None;
+        }
+        Some(Box::new(self.labels.iter().map(|(span, label)| {
+            LabeledSpan::new_with_span(Some(label.clone()), span.clone())
+        })))
     }
 }
 
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("Errors: {0:#?}")]
+    #[error("Invalid graph: {} error(s) found", .0.iter().filter(|e| !e.is_warning()).count())]
     InvalidGraph(Vec<ValidationError>),
 
     #[error("Invalid parameters")]
@@ -35,8 +188,20 @@ pub enum Error {
     #[error("Parse error: {0}")]
     ParseError(String),
 
+    /// Input that doesn't match the grammar of its format.
+    #[error("Syntax error: {message}")]
+    SyntaxError { message: String, span: Span },
+
     #[error("Render error: {0}")]
     RenderError(String),
+
+    /// A fork/join program that deadlocks, loses a thread or never ends.
+    #[error("Simulation failed: {0}")]
+    SimulationFailed(String),
+
+    /// A result too large for the integers it is computed with.
+    #[error("Overflow: {0}")]
+    Overflow(String),
 }
 
 impl From<Vec<ValidationError>> for Error {
@@ -44,3 +209,43 @@ impl From<Vec<ValidationError>> for Error {
         Error::InvalidGraph(errors)
     }
 }
+
+impl<R: pest::RuleType> From<pest::error::Error<R>> for Error {
+    fn from(error: pest::error::Error<R>) -> Self {
+        let span = match error.location {
+            pest::error::InputLocation::Pos(pos) => pos..pos,
+            pest::error::InputLocation::Span((start, end)) => start..end,
+        };
+        Error::SyntaxError {
+            message: error.variant.message().to_string(),
+            span,
+        }
+    }
+}
+
+impl Diagnostic for Error {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        match self {
+            Error::SyntaxError { .. } => Some(Box::new("SyntaxError")),
+            _ => None,
+        }
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        match self {
+            Error::SyntaxError { message, span } => Some(Box::new(std::iter::once(
+                LabeledSpan::new_with_span(Some(message.clone()), span.clone()),
+            ))),
+            _ => None,
+        }
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        match self {
+            Error::InvalidGraph(errors) => Some(Box::new(
+                errors.iter().map(|error| error as &dyn Diagnostic),
+            )),
+            _ => None,
+        }
+    }
+}
@@ -1,7 +1,12 @@
 use std::collections::{HashMap, HashSet};
 
+use log::debug;
+use petgraph::algo::dominators::simple_fast;
+use petgraph::graphmap::DiGraphMap;
+
 use super::fk;
 use super::ir;
+use crate::error::{Span, ValidationError, ValidationErrorKind};
 
 #[derive(Debug, Clone)]
 enum Region {
@@ -9,6 +14,7 @@ enum Region {
         name: String,
         deps: Vec<String>,
         terminal: bool,
+        attrs: ir::Attrs,
     },
     Sequence {
         regions: Vec<Region>,
@@ -16,6 +22,12 @@ enum Region {
     Parallel {
         branches: Vec<Region>,
     },
+    Loop {
+        body: Box<Region>,
+    },
+    Choice {
+        alternatives: Vec<Region>,
+    },
 }
 
 impl Region {
@@ -41,6 +53,8 @@ impl Region {
         match self {
             Region::Sequence { regions } => regions.is_empty(),
             Region::Parallel { branches } => branches.is_empty(),
+            Region::Loop { body } => body.is_empty(),
+            Region::Choice { alternatives } => alternatives.is_empty(),
             Region::Atomic { .. } => false,
         }
     }
@@ -52,12 +66,24 @@ pub struct ControlFlowGraph {
     edges: Vec<(usize, usize)>,
     labels: HashMap<String, usize>,
     label_at: HashMap<usize, String>,
+    /// Declared arity of every join counter (`c1 := 3`).
+    counters: HashMap<String, usize>,
+    /// Where every statement was written, for diagnostics.
+    spans: HashMap<usize, Span>,
+    /// First statement of every loop, with the `if`s that jump back to it,
+    /// outermost loop first.
+    loops: HashMap<usize, Vec<usize>>,
+    /// Every `if` that jumps back to the start of a loop.
+    latches: HashSet<usize>,
 }
 
 struct BuildCtx<'a> {
     join_labels: &'a HashMap<String, String>,
     dependency_join_labels: HashSet<String>,
     dependencies: &'a mut HashMap<String, Vec<String>>,
+    /// Latches of the loops whose body is being built, so their header isn't
+    /// taken for the same loop again.
+    open_loops: HashSet<usize>,
 }
 
 impl ControlFlowGraph {
@@ -67,6 +93,10 @@ impl ControlFlowGraph {
             edges: Vec::new(),
             labels: HashMap::new(),
             label_at: HashMap::new(),
+            counters: HashMap::new(),
+            spans: HashMap::new(),
+            loops: HashMap::new(),
+            latches: HashSet::new(),
         }
     }
 
@@ -78,36 +108,45 @@ impl ControlFlowGraph {
                 cfg.labels.insert(label.clone(), idx);
                 cfg.label_at.insert(idx, label.clone());
             }
-            println!("{idx} - {stmt:?}");
+            if let fk::Node::Counter { id, count } = &stmt.node {
+                cfg.counters.insert(id.clone(), *count);
+            }
+            if let Some(span) = &stmt.span {
+                cfg.spans.insert(idx, span.clone());
+            }
+            debug!("{idx} - {stmt:?}");
             cfg.nodes.insert(idx, stmt.node.clone());
         }
 
         for (idx, stmt) in graph.0.iter().enumerate() {
             match &stmt.node {
-                fk::Node::Final => {
-                    // No outgoing edges from final node
+                fk::Node::Final | fk::Node::Quit => {
+                    // No outgoing edges: the thread ends here
                 }
                 fk::Node::Goto { id: target_label } => {
-                    if target_label == "_end" {
-                        continue;
-                    }
                     if let Some(&target_idx) = cfg.labels.get(target_label) {
                         cfg.edges.push((idx, target_idx));
                     }
                 }
-                fk::Node::Fork { id: target_label } => {
+                fk::Node::Fork { id: target_label }
+                | fk::Node::If {
+                    id: target_label, ..
+                } => {
+                    // Both the new thread and the current one go on, or
+                    // either of them for an `if`.
                     if let Some(&target_idx) = cfg.labels.get(target_label) {
                         cfg.edges.push((idx, target_idx));
-                    } else if idx + 1 < graph.0.len() {
+                    }
+                    if idx + 1 < graph.0.len() {
                         cfg.edges.push((idx, idx + 1));
                     }
                 }
-                fk::Node::Atomic { id } if id == "end" => {
+                fk::Node::Atomic { id, .. } if id == "end" => {
                     if id == "end" {
                         continue;
                     }
                 }
-                fk::Node::Atomic { .. } | fk::Node::Join { .. } => {
+                fk::Node::Atomic { .. } | fk::Node::Join { .. } | fk::Node::Counter { .. } => {
                     if idx + 1 < graph.0.len() {
                         cfg.edges.push((idx, idx + 1));
                     }
@@ -115,9 +154,218 @@ impl ControlFlowGraph {
             }
         }
 
+        cfg.find_loops();
         cfg
     }
 
+    /// An `if` back to a statement that dominates it closes a loop, which ends
+    /// by falling through it. A plain `goto` back never leaves, so it isn't one.
+    fn find_loops(&mut self) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut graph = DiGraphMap::<usize, ()>::new();
+        for idx in 0..self.nodes.len() {
+            graph.add_node(idx);
+        }
+        for &(from, to) in &self.edges {
+            graph.add_edge(from, to, ());
+        }
+        let dominators = simple_fast(&graph, 0);
+
+        let back_edges: Vec<_> = self
+            .edges
+            .iter()
+            .copied()
+            .filter(|(from, to)| {
+                matches!(self.nodes.get(from), Some(fk::Node::If { id, .. }) if self.labels.get(id) == Some(to))
+                    && dominators
+                        .dominators(*from)
+                        .is_some_and(|mut dominators| dominators.any(|idx| idx == *to))
+            })
+            .collect();
+
+        for (from, to) in back_edges {
+            let latches = self.loops.entry(to).or_default();
+            latches.push(from);
+            latches.sort_unstable_by(|a, b| b.cmp(a));
+            self.latches.insert(from);
+        }
+    }
+
+    /// Reports the statements that never run, the forked threads that never
+    /// reach a join or the end of the program, and the gotos that jump into
+    /// the middle of another branch.
+    pub fn check_reachability(&self) -> Vec<ValidationError> {
+        let mut errors = vec![];
+        let reachable = self.reachable_from(0, true, None);
+
+        let mut unreachable: Vec<_> = (0..self.nodes.len())
+            .filter(|idx| !reachable.contains(idx))
+            .collect();
+        unreachable.sort_unstable();
+        for run in unreachable.chunk_by(|a, b| a + 1 == *b) {
+            let (first, last) = (run[0], run[run.len() - 1]);
+            let statements = if first == last {
+                format!("Statement #{first} can never run")
+            } else {
+                format!("Statements #{first} to #{last} can never run")
+            };
+            let span = self
+                .spans
+                .get(&first)
+                .zip(self.spans.get(&last))
+                .map(|(first, last)| first.start..last.end);
+            errors.push(
+                ValidationError::new(ValidationErrorKind::UnreachableStatement, statements)
+                    .with_label(span, "never runs")
+                    .with_help(
+                        "no fork or goto leads here, and the statement before never falls through",
+                    ),
+            );
+        }
+
+        for idx in 0..self.nodes.len() {
+            if !reachable.contains(&idx) {
+                continue;
+            }
+            let Some(fk::Node::Fork { id }) = self.nodes.get(&idx) else {
+                continue;
+            };
+            let Some(&target) = self.labels.get(id) else {
+                continue;
+            };
+            // Forks to a join only signal a dependency.
+            if matches!(self.nodes.get(&target), Some(fk::Node::Join { .. })) {
+                continue;
+            }
+
+            let thread = self.reachable_from(target, true, None);
+            let finishes = thread.iter().any(|&stmt| {
+                matches!(self.nodes.get(&stmt), Some(fk::Node::Join { .. }))
+                    || self.ends_program(stmt)
+            });
+            if !finishes {
+                errors.push(
+                    ValidationError::new(
+                        ValidationErrorKind::DanglingBranch,
+                        format!(
+                            "Thread forked at #{idx} to '{id}' never reaches a join or the end of the program"
+                        ),
+                    )
+                    .with_label(self.spans.get(&idx).cloned(), "forked here")
+                    .with_label(self.spans.get(&target).cloned(), "this branch never joins")
+                    .with_help("end the branch with a `goto` to the join that waits for it, or with `quit`"),
+                );
+            }
+        }
+
+        let entries = self.branch_entries();
+        // The alternatives an `if` jumps to go back to where they all meet,
+        // even after joining the branches they fork.
+        let merges: HashSet<_> = self
+            .nodes
+            .values()
+            .filter_map(|node| match node {
+                fk::Node::If { id, .. } => self.labels.get(id).and_then(|&t| self.find_merge(t)),
+                _ => None,
+            })
+            .collect();
+        for idx in 0..self.nodes.len() {
+            if !reachable.contains(&idx) {
+                continue;
+            }
+            let Some(fk::Node::Goto { id }) = self.nodes.get(&idx) else {
+                continue;
+            };
+            let Some(&target) = self.labels.get(id) else {
+                continue;
+            };
+            if entries.contains(&target) || merges.contains(&target) {
+                continue;
+            }
+
+            // The target belongs to a branch that gets there on its own.
+            let is_foreign = entries.iter().any(|&entry| {
+                let branch = self.reachable_from(entry, false, Some(idx));
+                branch.contains(&target) && !branch.contains(&idx)
+            });
+            if is_foreign {
+                errors.push(
+                    ValidationError::new(
+                        ValidationErrorKind::GotoIntoBranch,
+                        format!("goto@#{idx} jumps into the middle of another branch at '{id}'"),
+                    )
+                    .with_label(self.spans.get(&idx).cloned(), "jumps from here")
+                    .with_label(self.spans.get(&target).cloned(), "into this branch")
+                    .with_help("branches can only meet at a join"),
+                );
+            }
+        }
+
+        errors
+    }
+
+    /// Statements reachable from `start`, without leaving `cut` if given.
+    /// Joins are where branches meet, so a single branch (`across_branches`
+    /// unset) stops before them and doesn't follow the threads it forks.
+    fn reachable_from(
+        &self,
+        start: usize,
+        across_branches: bool,
+        cut: Option<usize>,
+    ) -> HashSet<usize> {
+        let mut visited = HashSet::new();
+        let mut pending = vec![start];
+
+        while let Some(idx) = pending.pop() {
+            if !self.nodes.contains_key(&idx) || !visited.insert(idx) || Some(idx) == cut {
+                continue;
+            }
+            let forked = match self.nodes.get(&idx) {
+                Some(fk::Node::Fork { id }) => self.labels.get(id).copied(),
+                _ => None,
+            };
+            for &(_, to) in self.edges.iter().filter(|(from, _)| *from == idx) {
+                let is_join = matches!(self.nodes.get(&to), Some(fk::Node::Join { .. }));
+                if !across_branches && (is_join || Some(to) == forked) {
+                    continue;
+                }
+                pending.push(to);
+            }
+        }
+
+        visited
+    }
+
+    /// Where the program, every forked thread and every continuation after a
+    /// join start.
+    fn branch_entries(&self) -> Vec<usize> {
+        let mut entries = vec![0];
+        for (&idx, node) in &self.nodes {
+            match node {
+                fk::Node::Fork { id } => entries.extend(self.labels.get(id)),
+                fk::Node::Join { .. } if self.nodes.contains_key(&(idx + 1)) => {
+                    entries.push(idx + 1);
+                }
+                _ => {}
+            }
+        }
+        entries
+    }
+
+    /// Whether the thread finishes at `idx`, ending the program or quitting.
+    /// Running past the last statement isn't finishing: the thread is lost.
+    fn ends_program(&self, idx: usize) -> bool {
+        match self.nodes.get(&idx) {
+            Some(fk::Node::Final | fk::Node::Quit) => true,
+            Some(fk::Node::Atomic { id, .. }) => id == "end",
+            Some(fk::Node::Goto { id }) => id == "end" || id == "_end",
+            _ => false,
+        }
+    }
+
     /// Main function to map from Fork/Join to IR.
     pub fn to_ir(&self) -> ir::Graph {
         let join_labels = self.collect_join_labels();
@@ -127,6 +375,7 @@ impl ControlFlowGraph {
             join_labels: &join_labels,
             dependency_join_labels,
             dependencies: &mut dependencies,
+            open_loops: HashSet::new(),
         };
         let region = self.build_from_index(0, &mut HashSet::new(), &mut ctx);
         let region = Self::apply_dependencies(region, &dependencies);
@@ -145,19 +394,19 @@ impl ControlFlowGraph {
         let mut idx = 0;
 
         while self.nodes.contains_key(&idx) {
-            if let Some(fk::Node::Join { .. }) = self.nodes.get(&idx) {
-                if let Some(label) = self.label_at.get(&idx) {
-                    let mut next = idx + 1;
-                    while let Some(node) = self.nodes.get(&next) {
-                        if let fk::Node::Atomic { id } = node {
-                            join_labels.insert(label.clone(), id.clone());
-                            break;
-                        }
-                        if matches!(node, fk::Node::Final) {
-                            break;
-                        }
-                        next += 1;
+            if let Some(fk::Node::Join { .. }) = self.nodes.get(&idx)
+                && let Some(label) = self.label_at.get(&idx)
+            {
+                let mut next = idx + 1;
+                while let Some(node) = self.nodes.get(&next) {
+                    if let fk::Node::Atomic { id, .. } = node {
+                        join_labels.insert(label.clone(), id.clone());
+                        break;
+                    }
+                    if matches!(node, fk::Node::Final) {
+                        break;
                     }
+                    next += 1;
                 }
             }
             idx += 1;
@@ -172,10 +421,10 @@ impl ControlFlowGraph {
     ) -> HashSet<String> {
         let mut labels = HashSet::new();
         for node in self.nodes.values() {
-            if let fk::Node::Fork { id } = node {
-                if join_labels.contains_key(id) {
-                    labels.insert(id.clone());
-                }
+            if let fk::Node::Fork { id } = node
+                && join_labels.contains_key(id)
+            {
+                labels.insert(id.clone());
             }
         }
         labels
@@ -207,12 +456,31 @@ impl ControlFlowGraph {
                 break;
             }
 
+            if let Some(&latch) = self
+                .loops
+                .get(&current)
+                .and_then(|latches| latches.iter().find(|latch| !ctx.open_loops.contains(latch)))
+            {
+                ctx.open_loops.insert(latch);
+                let body = self.build_branch_until(current, Some(latch), global_visited, ctx);
+                regions.push(Region::Loop {
+                    body: Box::new(body),
+                });
+                global_visited.insert(latch);
+                current = latch + 1;
+                continue;
+            }
+
             match node {
-                fk::Node::Atomic { id } if id == "end" => {
+                fk::Node::Atomic { id, .. } if id == "end" => {
+                    global_visited.insert(current);
+                    current += 1;
+                }
+                fk::Node::Counter { .. } => {
                     global_visited.insert(current);
                     current += 1;
                 }
-                fk::Node::Atomic { id: name } => {
+                fk::Node::Atomic { id: name, duration } => {
                     global_visited.insert(current);
                     let (dependents, terminal) = self.analyze_atomic(current, None, ctx);
                     Self::record_dependencies(ctx.dependencies, name, &dependents);
@@ -220,6 +488,11 @@ impl ControlFlowGraph {
                         name: name.clone(),
                         deps: Vec::new(),
                         terminal,
+                        attrs: ir::Attrs {
+                            duration: *duration,
+                            span: self.spans.get(&current).cloned(),
+                            ..ir::Attrs::default()
+                        },
                     });
                     current += 1;
                 }
@@ -278,6 +551,14 @@ impl ControlFlowGraph {
                         break;
                     }
                 }
+                fk::Node::If { .. } => {
+                    let (choice, merge) = self.build_choice(current, global_visited, ctx);
+                    regions.push(choice);
+                    let Some(merge) = merge else {
+                        break;
+                    };
+                    current = merge;
+                }
                 fk::Node::Join { .. } => {
                     // Join encountered outside of fork processing - skip it
                     global_visited.insert(current);
@@ -301,6 +582,10 @@ impl ControlFlowGraph {
                         break;
                     }
                 }
+                fk::Node::Quit => {
+                    global_visited.insert(current);
+                    break;
+                }
                 fk::Node::Final => {}
             }
         }
@@ -342,12 +627,31 @@ impl ControlFlowGraph {
                 break;
             }
 
+            if let Some(&latch) = self
+                .loops
+                .get(&current)
+                .and_then(|latches| latches.iter().find(|latch| !ctx.open_loops.contains(latch)))
+            {
+                ctx.open_loops.insert(latch);
+                let body = self.build_branch_until(current, Some(latch), global_visited, ctx);
+                regions.push(Region::Loop {
+                    body: Box::new(body),
+                });
+                global_visited.insert(latch);
+                current = latch + 1;
+                continue;
+            }
+
             match node {
-                fk::Node::Atomic { id } if id == "end" => {
+                fk::Node::Atomic { id, .. } if id == "end" => {
+                    global_visited.insert(current);
+                    current += 1;
+                }
+                fk::Node::Counter { .. } => {
                     global_visited.insert(current);
                     current += 1;
                 }
-                fk::Node::Atomic { id: name } => {
+                fk::Node::Atomic { id: name, duration } => {
                     global_visited.insert(current);
                     let (dependents, terminal) = self.analyze_atomic(current, join_idx, ctx);
                     Self::record_dependencies(ctx.dependencies, name, &dependents);
@@ -355,6 +659,11 @@ impl ControlFlowGraph {
                         name: name.clone(),
                         deps: Vec::new(),
                         terminal,
+                        attrs: ir::Attrs {
+                            duration: *duration,
+                            span: self.spans.get(&current).cloned(),
+                            ..ir::Attrs::default()
+                        },
                     });
                     current += 1;
                 }
@@ -413,11 +722,22 @@ impl ControlFlowGraph {
                         break;
                     }
                 }
-                fk::Node::Join { .. } => {
-                    // Skip dependency joins inside a branch
-                    if let Some(label) = self.label_at.get(&current)
-                        && ctx.dependency_join_labels.contains(label)
-                    {
+                fk::Node::If { .. } => {
+                    let (choice, merge) = self.build_choice(current, global_visited, ctx);
+                    regions.push(choice);
+                    let Some(merge) = merge else {
+                        break;
+                    };
+                    current = merge;
+                }
+                fk::Node::Join { id } => {
+                    // Skip dependency joins inside a branch, and the ones
+                    // only this branch arrives at.
+                    let is_dependency = self
+                        .label_at
+                        .get(&current)
+                        .is_some_and(|label| ctx.dependency_join_labels.contains(label));
+                    if is_dependency || self.counters.get(id) == Some(&1) {
                         global_visited.insert(current);
                         current += 1;
                         continue;
@@ -442,6 +762,10 @@ impl ControlFlowGraph {
                     }
                     current = target_idx;
                 }
+                fk::Node::Quit => {
+                    global_visited.insert(current);
+                    break;
+                }
                 fk::Node::Final => {}
             }
         }
@@ -460,6 +784,10 @@ impl ControlFlowGraph {
         let mut current = idx + 1;
 
         while let Some(node) = self.nodes.get(&current) {
+            // Where the branch ends belongs to whatever follows it.
+            if Some(current) == stop_join_idx {
+                break;
+            }
             match node {
                 fk::Node::Fork { id } => {
                     if let Some(dep) = ctx.join_labels.get(id) {
@@ -470,8 +798,12 @@ impl ControlFlowGraph {
                     }
                     break;
                 }
+                fk::Node::Quit => {
+                    terminal = true;
+                    break;
+                }
                 fk::Node::Goto { id } => {
-                    let is_structural_join = self.labels.get(id).map(|i| *i) == stop_join_idx;
+                    let is_structural_join = self.labels.get(id).copied() == stop_join_idx;
 
                     if !is_structural_join && let Some(dep) = ctx.join_labels.get(id) {
                         ctx.dependency_join_labels.insert(id.clone());
@@ -494,12 +826,19 @@ impl ControlFlowGraph {
         // The join is typically where all branches converge
 
         // First, skip over any consecutive forks (they share the same join)
-        let mut current = fork_idx + 1;
-        while let Some(fk::Node::Fork { .. }) = self.nodes.get(&current) {
+        // and count the threads that will meet at it.
+        let mut threads = 1;
+        let mut current = fork_idx;
+        while let Some(fk::Node::Fork { id }) = self.nodes.get(&current) {
+            if !ctx.dependency_join_labels.contains(id) {
+                threads += 1;
+            }
             current += 1;
         }
 
         let mut visited = HashSet::new();
+        // A join whose declared arity doesn't match, kept in case none does.
+        let mut fallback = None;
 
         while let Some(node) = self.nodes.get(&current) {
             if visited.contains(&current) {
@@ -508,14 +847,20 @@ impl ControlFlowGraph {
             visited.insert(current);
 
             match node {
-                fk::Node::Join { .. } => {
+                fk::Node::Join { id } => {
                     if let Some(label) = self.label_at.get(&current)
                         && ctx.dependency_join_labels.contains(label)
                     {
                         current += 1;
                         continue;
                     }
-                    return Some(current);
+                    match self.counters.get(id) {
+                        Some(&count) if count != threads => {
+                            fallback.get_or_insert(current);
+                            current += 1;
+                        }
+                        _ => return Some(current),
+                    }
                 }
                 fk::Node::Fork { id } => {
                     if ctx.dependency_join_labels.contains(id) {
@@ -529,7 +874,12 @@ impl ControlFlowGraph {
                         current += 1;
                     }
                 }
-                fk::Node::Atomic { .. } | fk::Node::Final => {
+                // Take the first alternative, it falls through to where the
+                // others end.
+                fk::Node::Atomic { .. }
+                | fk::Node::Final
+                | fk::Node::Counter { .. }
+                | fk::Node::If { .. } => {
                     current += 1;
                 }
                 fk::Node::Goto { id: target } => {
@@ -542,6 +892,54 @@ impl ControlFlowGraph {
                         break;
                     }
                 }
+                fk::Node::Quit => break,
+            }
+        }
+
+        fallback
+    }
+
+    /// Builds the alternatives of the `if`s at `start`: the one that falls
+    /// through them and the ones they jump to, up to where they meet again,
+    /// which is returned too.
+    fn build_choice(
+        &self,
+        start: usize,
+        global_visited: &mut HashSet<usize>,
+        ctx: &mut BuildCtx<'_>,
+    ) -> (Region, Option<usize>) {
+        let mut targets = vec![];
+        let mut current = start;
+        while let Some(fk::Node::If { id, .. }) = self.nodes.get(&current) {
+            global_visited.insert(current);
+            targets.extend(self.labels.get(id).copied());
+            current += 1;
+        }
+
+        let merge = targets.first().and_then(|&target| self.find_merge(target));
+        let alternatives = std::iter::once(current)
+            .chain(targets)
+            .map(|alternative| self.build_branch_until(alternative, merge, global_visited, ctx))
+            .filter(|region| !region.is_empty())
+            .collect();
+
+        (Region::Choice { alternatives }, merge)
+    }
+
+    /// Where an alternative that was jumped to meets the others: the target of
+    /// the `goto` that ends it.
+    fn find_merge(&self, start: usize) -> Option<usize> {
+        let mut current = start;
+        let mut visited = HashSet::new();
+
+        while let Some(node) = self.nodes.get(&current) {
+            if !visited.insert(current) {
+                break;
+            }
+            match node {
+                fk::Node::Goto { id } => return self.labels.get(id).copied(),
+                fk::Node::Quit | fk::Node::Final => break,
+                _ => current += 1,
             }
         }
 
@@ -554,12 +952,14 @@ impl ControlFlowGraph {
                 name,
                 deps: _,
                 terminal,
+                attrs,
             } => {
                 let deps = dependencies.get(&name).cloned().unwrap_or_default();
                 Region::Atomic {
                     name,
                     deps,
                     terminal,
+                    attrs,
                 }
             }
             Region::Sequence { regions } => {
@@ -575,6 +975,15 @@ impl ControlFlowGraph {
                     .map(|region| Self::apply_dependencies(region, dependencies))
                     .collect(),
             },
+            Region::Loop { body } => Region::Loop {
+                body: Box::new(Self::apply_dependencies(*body, dependencies)),
+            },
+            Region::Choice { alternatives } => Region::Choice {
+                alternatives: alternatives
+                    .into_iter()
+                    .map(|region| Self::apply_dependencies(region, dependencies))
+                    .collect(),
+            },
         }
     }
 
@@ -603,10 +1012,12 @@ impl ControlFlowGraph {
                 name,
                 deps,
                 terminal,
+                attrs,
             } => ir::Node::Atomic(
                 name.clone(),
                 deps.iter().map(|dep| ir::Node::Dep(dep.clone())).collect(),
                 *terminal,
+                attrs.clone(),
             ),
             Region::Sequence { regions } => {
                 let ir_nodes: Vec<_> = regions.iter().map(Self::region_to_ir).collect();
@@ -624,6 +1035,176 @@ impl ControlFlowGraph {
                     _ => ir::Node::Par(ir_branches),
                 }
             }
+            Region::Loop { body } => match Self::region_to_ir(body) {
+                ir::Node::Seq(children) => ir::Node::Loop(children),
+                other => ir::Node::Loop(vec![other]),
+            },
+            Region::Choice { alternatives } => {
+                let mut ir_alternatives: Vec<_> =
+                    alternatives.iter().map(Self::region_to_ir).collect();
+                match ir_alternatives.len() {
+                    1 => ir_alternatives.remove(0),
+                    _ => ir::Node::Choice(ir_alternatives),
+                }
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(input: &str) -> Vec<ValidationErrorKind> {
+        let graph = fk::parse(input).unwrap();
+        ControlFlowGraph::from_graph(&graph)
+            .check_reachability()
+            .into_iter()
+            .map(|error| error.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_well_formed_program() {
+        let kinds = check(
+            "begin\nc1 := 2\ns1\nfork L2\ns2\nL1: join c1\ns4\ngoto _end\nL2: s3\ngoto L1\n_end: end\nend",
+        );
+
+        assert!(kinds.is_empty());
+    }
+
+    #[test]
+    fn test_unreachable_and_dangling() {
+        let kinds = check(
+            "begin\nc1 := 2\nfork L2\ns1\nL1: join c1\ngoto _end\ns9\n_end: end\nL2: s2\nend",
+        );
+
+        assert_eq!(
+            kinds,
+            vec![
+                ValidationErrorKind::UnreachableStatement,
+                ValidationErrorKind::DanglingBranch
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quit_ends_the_thread() {
+        let input = "begin\ns0\nquit\ns1\nend";
+        let cfg = ControlFlowGraph::from_graph(&fk::parse(input).unwrap());
+
+        assert!(cfg.edges.iter().all(|&(from, _)| from != 1));
+        assert_eq!(
+            check(input),
+            vec![ValidationErrorKind::UnreachableStatement]
+        );
+    }
+
+    #[test]
+    fn test_goto_into_branch() {
+        let kinds = check(
+            "begin\nc1 := 2\nfork L2\ns1\ngoto L3\nL1: join c1\ngoto _end\nL2: s2\nL3: s3\ngoto L1\n_end: end\nend",
+        );
+
+        assert_eq!(kinds, vec![ValidationErrorKind::GotoIntoBranch]);
+    }
+
+    #[test]
+    fn test_join_only_the_branch_reaches() {
+        let nodes = ir::parse("$s0,{[s1,s2#{s1}],s3}$").unwrap().0;
+        let graph = fk::Graph::from_ir(&ir::Graph::new(nodes));
+        let cfg = ControlFlowGraph::from_graph(&graph);
+
+        assert!(cfg.check_reachability().is_empty());
+        let ir = crate::graph::Graph::<ir::Node, crate::graph::Ir>::new(cfg.to_ir().0);
+        assert_eq!(ir.to_string(), "$s0,{[s1,s2],s3}$");
+    }
+
+    #[test]
+    fn test_backward_if_is_a_loop() {
+        let graph =
+            fk::parse("begin\ns0\nR: s1\ns2\nif k1 goto R\ns3\ngoto _end\n_end: end\nend").unwrap();
+        let cfg = ControlFlowGraph::from_graph(&graph);
+
+        assert!(cfg.check_reachability().is_empty());
+        let ir = crate::graph::Graph::<ir::Node, crate::graph::Ir>::new(cfg.to_ir().0);
+        assert_eq!(ir.to_string(), "$s0,[s1,s2]*,s3$");
+    }
+
+    #[test]
+    fn test_backward_goto_never_leaves() {
+        let kinds = check("begin\ns0\nR: s1\ns2\ngoto R\ns3\ngoto _end\n_end: end\nend");
+
+        assert_eq!(kinds, vec![ValidationErrorKind::UnreachableStatement]);
+    }
+
+    #[test]
+    fn test_loop_round_trip() {
+        let nodes = ir::parse("$s0,[s1,{s2,s3}]*,s4$").unwrap().0;
+        let graph = fk::Graph::from_ir(&ir::Graph::new(nodes));
+        let cfg = ControlFlowGraph::from_graph(&graph);
+
+        assert!(cfg.check_reachability().is_empty());
+        let ir = crate::graph::Graph::<ir::Node, crate::graph::Ir>::new(cfg.to_ir().0);
+        assert_eq!(ir.to_string(), "$s0,[s1,{s2,s3}]*,s4$");
+    }
+
+    #[test]
+    fn test_choice_of_parallel_round_trip() {
+        let nodes = ir::parse("$s0,([s1,s2]|{s3,s4}),s5$").unwrap().0;
+        let graph = fk::Graph::from_ir(&ir::Graph::new(nodes));
+        let cfg = ControlFlowGraph::from_graph(&graph);
+
+        assert!(cfg.check_reachability().is_empty());
+        let ir = crate::graph::Graph::<ir::Node, crate::graph::Ir>::new(cfg.to_ir().0);
+        assert_eq!(ir.to_string(), "$s0,([s1,s2]|{s3,s4}),s5$");
+    }
+
+    #[test]
+    fn test_terminal_branch_round_trip() {
+        let nodes = ir::parse("$s0,{s3,[s1,s2!]},s4$").unwrap().0;
+        let graph = fk::Graph::from_ir(&ir::Graph::new(nodes));
+        let cfg = ControlFlowGraph::from_graph(&graph);
+
+        assert!(cfg.check_reachability().is_empty());
+        let ir = crate::graph::Graph::<ir::Node, crate::graph::Ir>::new(cfg.to_ir().0);
+        assert_eq!(ir.to_string(), "$s0,{s3,[s1,s2!]},s4$");
+    }
+
+    #[test]
+    fn test_conditional_goto_is_a_choice() {
+        let graph = fk::parse(
+            "begin\ns0\nif k1 goto L2\ns1\nM1: s3\ngoto _end\n_end: end\nL2: s2\ngoto M1\nend",
+        )
+        .unwrap();
+        let cfg = ControlFlowGraph::from_graph(&graph);
+
+        assert!(cfg.check_reachability().is_empty());
+        let ir = crate::graph::Graph::<ir::Node, crate::graph::Ir>::new(cfg.to_ir().0);
+        assert_eq!(ir.to_string(), "$s0,(s1|s2),s3$");
+    }
+
+    #[test]
+    fn test_fork_pairs_with_the_join_of_its_arity() {
+        let join_for = |input: &str| {
+            let cfg = ControlFlowGraph::from_graph(&fk::parse(input).unwrap());
+            let ctx = BuildCtx {
+                join_labels: &HashMap::new(),
+                dependency_join_labels: HashSet::new(),
+                dependencies: &mut HashMap::new(),
+                open_loops: HashSet::new(),
+            };
+            cfg.find_join_for_fork(2, &ctx)
+        };
+        let program = "fork L2\nfork L3\ns1\nJ1: join c2\nJ2: join c1\ngoto _end\n\
+                       L2: s2\ngoto J2\nL3: s3\ngoto J1\n_end: end\nend";
+
+        // Three threads leave the forks, so `J2` is the join that waits for them.
+        assert_eq!(
+            join_for(&format!("begin\nc1 := 3\nc2 := 2\n{program}")),
+            Some(6)
+        );
+        // Without counters (two tasks keep the indices) the first join is taken.
+        assert_eq!(join_for(&format!("begin\ns8\ns9\n{program}")), Some(5));
+    }
+}
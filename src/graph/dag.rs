@@ -0,0 +1,380 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use petgraph::visit::EdgeRef;
+
+use super::ir;
+
+/// Plain precedence DAG between tasks, used to rebuild the most structured IR
+/// for it: series and parallel compositions become `[]`/`{}` and only the
+/// edges that break that structure are kept as `#{}` dependencies.
+#[derive(Debug, Default)]
+pub struct Dag {
+    names: Vec<String>,
+    attrs: Vec<ir::Attrs>,
+    edges: BTreeSet<(usize, usize)>,
+}
+
+#[derive(Debug)]
+enum Tree {
+    Leaf(usize),
+    Seq(Vec<Tree>),
+    Par(Vec<Tree>),
+}
+
+impl Dag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_node(&mut self, name: String, attrs: ir::Attrs) -> usize {
+        self.names.push(name);
+        self.attrs.push(attrs);
+        self.names.len() - 1
+    }
+
+    pub fn add_edge(&mut self, from: usize, to: usize) {
+        if from != to {
+            self.edges.insert((from, to));
+        }
+    }
+
+    /// Takes every edge as a precedence constraint, whatever its weight.
+    pub fn from_petgraph<E>(graph: &petgraph::Graph<String, E>) -> Self {
+        let mut dag = Dag::new();
+        for idx in graph.node_indices() {
+            dag.add_node(graph[idx].clone(), ir::Attrs::default());
+        }
+        for edge in graph.edge_references() {
+            dag.add_edge(edge.source().index(), edge.target().index());
+        }
+        dag.transitive_reduction();
+        dag
+    }
+
+    /// Data dependencies of a sequential program: a task depends on every earlier
+    /// task it conflicts with according to Bernstein's conditions.
+    pub fn from_accesses(tasks: Vec<(String, ir::Attrs)>) -> Self {
+        let mut dag = Dag::new();
+        for (name, attrs) in tasks {
+            dag.add_node(name, attrs);
+        }
+        for later in 0..dag.names.len() {
+            for earlier in 0..later {
+                if !dag.attrs[earlier]
+                    .conflicts_with(&dag.attrs[later])
+                    .is_empty()
+                {
+                    dag.add_edge(earlier, later);
+                }
+            }
+        }
+        dag.transitive_reduction();
+        dag
+    }
+
+    /// Drops every edge implied by a longer path.
+    pub fn transitive_reduction(&mut self) {
+        let all: Vec<_> = (0..self.names.len()).collect();
+        let edges: Vec<_> = self.edges.iter().copied().collect();
+        for (from, to) in edges {
+            self.edges.remove(&(from, to));
+            if !self.reachable(from, &all, &self.edges).contains(&to) {
+                self.edges.insert((from, to));
+            }
+        }
+    }
+
+    pub fn to_ir(&self) -> Vec<ir::Node> {
+        if self.names.is_empty() {
+            return vec![];
+        }
+
+        let order = self.topological_order();
+        let mut structural = self.edges.clone();
+        let mut deps = vec![];
+        let tree = self.decompose(order, &mut structural, &mut deps);
+
+        let mut deps_of: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (from, to) in deps {
+            deps_of.entry(to).or_default().push(from);
+        }
+
+        match self.tree_to_ir(&tree, &deps_of) {
+            ir::Node::Seq(children) => children,
+            node => vec![node],
+        }
+    }
+
+    fn topological_order(&self) -> Vec<usize> {
+        let mut indegree = vec![0; self.names.len()];
+        for &(_, to) in &self.edges {
+            indegree[to] += 1;
+        }
+
+        let mut ready: BTreeSet<_> = (0..self.names.len())
+            .filter(|&n| indegree[n] == 0)
+            .collect();
+        let mut order = vec![];
+        while let Some(n) = ready.pop_first() {
+            order.push(n);
+            for &(from, to) in &self.edges {
+                if from == n {
+                    indegree[to] -= 1;
+                    if indegree[to] == 0 {
+                        ready.insert(to);
+                    }
+                }
+            }
+        }
+        order
+    }
+
+    /// Nodes of `set` reachable from `start` following `edges` inside `set`.
+    fn reachable(
+        &self,
+        start: usize,
+        set: &[usize],
+        edges: &BTreeSet<(usize, usize)>,
+    ) -> HashSet<usize> {
+        let members: HashSet<_> = set.iter().copied().collect();
+        let mut seen = HashSet::new();
+        let mut stack = vec![start];
+        while let Some(n) = stack.pop() {
+            for &(from, to) in edges {
+                if from == n && members.contains(&to) && seen.insert(to) {
+                    stack.push(to);
+                }
+            }
+        }
+        seen
+    }
+
+    /// Weakly connected components of `set`, each in topological order.
+    fn components(set: &[usize], edges: &BTreeSet<(usize, usize)>) -> Vec<Vec<usize>> {
+        let position: HashMap<_, _> = set.iter().enumerate().map(|(i, &n)| (n, i)).collect();
+        let mut component: Vec<usize> = (0..set.len()).collect();
+
+        fn find(component: &mut [usize], i: usize) -> usize {
+            if component[i] != i {
+                component[i] = find(component, component[i]);
+            }
+            component[i]
+        }
+
+        for (from, to) in edges {
+            if let (Some(&a), Some(&b)) = (position.get(from), position.get(to)) {
+                let (a, b) = (find(&mut component, a), find(&mut component, b));
+                component[a.max(b)] = a.min(b);
+            }
+        }
+
+        let mut groups: Vec<(usize, Vec<usize>)> = vec![];
+        for (i, &n) in set.iter().enumerate() {
+            let root = find(&mut component, i);
+            match groups.iter_mut().find(|(r, _)| *r == root) {
+                Some((_, group)) => group.push(n),
+                None => groups.push((root, vec![n])),
+            }
+        }
+        groups.into_iter().map(|(_, group)| group).collect()
+    }
+
+    /// `set` must be in topological order.
+    fn decompose(
+        &self,
+        set: Vec<usize>,
+        structural: &mut BTreeSet<(usize, usize)>,
+        deps: &mut Vec<(usize, usize)>,
+    ) -> Tree {
+        if set.len() == 1 {
+            return Tree::Leaf(set[0]);
+        }
+
+        let components = Self::components(&set, structural);
+        if components.len() > 1 {
+            return Tree::Par(
+                components
+                    .into_iter()
+                    .map(|component| self.decompose(component, structural, deps))
+                    .collect(),
+            );
+        }
+
+        // Every task before a series cut precedes every task after it, so any
+        // topological order has the cut as a prefix.
+        let reach: Vec<_> = set
+            .iter()
+            .map(|&n| self.reachable(n, &set, structural))
+            .collect();
+        let mut blocks = vec![];
+        let mut start = 0;
+        for cut in 1..set.len() {
+            let is_cut = (0..cut).all(|i| set[cut..].iter().all(|n| reach[i].contains(n)));
+            if is_cut {
+                blocks.push(set[start..cut].to_vec());
+                start = cut;
+            }
+        }
+
+        if !blocks.is_empty() {
+            blocks.push(set[start..].to_vec());
+            let mut children = vec![];
+            for block in blocks {
+                match self.decompose(block, structural, deps) {
+                    Tree::Seq(inner) => children.extend(inner),
+                    other => children.push(other),
+                }
+            }
+            return Tree::Seq(children);
+        }
+
+        // Not series-parallel: demote edges to explicit dependencies until it is.
+        match Self::best_bridge(&set, structural) {
+            Some(edge) => {
+                structural.remove(&edge);
+                deps.push(edge);
+            }
+            None => {
+                // Keep a single incoming edge per task (from the latest predecessor),
+                // which leaves a forest and is always decomposable.
+                for &n in &set {
+                    let mut preds: Vec<_> = set
+                        .iter()
+                        .copied()
+                        .filter(|&p| structural.contains(&(p, n)))
+                        .collect();
+                    preds.pop();
+                    for p in preds {
+                        structural.remove(&(p, n));
+                        deps.push((p, n));
+                    }
+                }
+            }
+        }
+
+        self.decompose(set, structural, deps)
+    }
+
+    /// The edge whose removal splits `set` into the most balanced two parts.
+    fn best_bridge(set: &[usize], structural: &BTreeSet<(usize, usize)>) -> Option<(usize, usize)> {
+        let members: HashSet<_> = set.iter().copied().collect();
+        let mut best: Option<((usize, usize), usize)> = None;
+
+        for &edge in structural {
+            if !members.contains(&edge.0) || !members.contains(&edge.1) {
+                continue;
+            }
+            let mut without = structural.clone();
+            without.remove(&edge);
+            let components = Self::components(set, &without);
+            if components.len() < 2 {
+                continue;
+            }
+            let smallest = components.iter().map(Vec::len).min().unwrap_or(0);
+            if best.is_none_or(|(_, size)| smallest > size) {
+                best = Some((edge, smallest));
+            }
+        }
+
+        best.map(|(edge, _)| edge)
+    }
+
+    fn tree_to_ir(&self, tree: &Tree, deps_of: &HashMap<usize, Vec<usize>>) -> ir::Node {
+        match tree {
+            Tree::Leaf(n) => {
+                let mut deps = deps_of.get(n).cloned().unwrap_or_default();
+                deps.sort_unstable();
+                ir::Node::Atomic(
+                    self.names[*n].clone(),
+                    deps.into_iter()
+                        .map(|d| ir::Node::Dep(self.names[d].clone()))
+                        .collect(),
+                    false,
+                    self.attrs[*n].clone(),
+                )
+            }
+            Tree::Seq(children) => ir::Node::Seq(
+                children
+                    .iter()
+                    .map(|child| self.tree_to_ir(child, deps_of))
+                    .collect(),
+            ),
+            Tree::Par(children) => ir::Node::Par(
+                children
+                    .iter()
+                    .map(|child| self.tree_to_ir(child, deps_of))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use petgraph::visit::Dfs;
+
+    use super::*;
+    use crate::graph::{Graph, Ir, Valid};
+
+    /// Every `(from, to)` pair of task names such that `to` runs after `from`.
+    fn closure(nodes: Vec<ir::Node>) -> BTreeSet<(String, String)> {
+        let graph = Graph::<ir::Node, Ir, Valid>::new(nodes).to_petgraph();
+        let mut pairs = BTreeSet::new();
+        for start in graph.node_indices() {
+            let mut dfs = Dfs::new(&graph, start);
+            while let Some(next) = dfs.next(&graph) {
+                if next != start {
+                    pairs.insert((graph[start].clone(), graph[next].clone()));
+                }
+            }
+        }
+        pairs
+    }
+
+    fn round_trip(input: &str) -> Vec<ir::Node> {
+        let nodes = ir::parse(input).unwrap().0;
+        let graph = Graph::<ir::Node, Ir, Valid>::new(nodes.clone()).to_petgraph();
+        let rebuilt = Dag::from_petgraph(&graph).to_ir();
+        assert_eq!(closure(nodes), closure(rebuilt.clone()));
+        rebuilt
+    }
+
+    #[test]
+    fn test_series_parallel_round_trip() {
+        let rebuilt = round_trip("$s0,{[s1,s2],[s3,{s4,s5}]},s6,{s7,s8,s9},s10$");
+        let printed = Graph::<ir::Node, Ir, Valid>::new(rebuilt).to_string();
+
+        assert_eq!(printed, "$s0,{[s1,s2],[s3,{s4,s5}]},s6,{s7,s8,s9},s10$");
+    }
+
+    #[test]
+    fn test_non_series_parallel_round_trip() {
+        round_trip("$s0,{[s1,s2#{s4},s3],[s4,s7,s8],s5},s6$");
+        round_trip("$s0,s1,{[s2,s5#{s3},s8#{s6}],[s3,s6!],[s4,s7,s9#{s6}]},sa$");
+    }
+
+    #[test]
+    fn test_conflicting_accesses_are_ordered() {
+        let task = |name: &str, reads: &[&str], writes: &[&str]| {
+            let attrs = ir::Attrs {
+                reads: reads.iter().map(|var| var.to_string()).collect(),
+                writes: writes.iter().map(|var| var.to_string()).collect(),
+                ..ir::Attrs::default()
+            };
+            (name.to_string(), attrs)
+        };
+        let dag = Dag::from_accesses(vec![
+            task("s0", &[], &["a"]),
+            task("s1", &["a"], &[]),
+            task("s2", &[], &["a"]),
+            task("s3", &[], &["b"]),
+            task("s4", &["b"], &["a"]),
+        ]);
+
+        // Read after write, write after read and write after write, without the
+        // edges implied by others such as `s0 -> s2`.
+        assert_eq!(dag.edges, BTreeSet::from([(0, 1), (1, 2), (2, 4), (3, 4)]));
+    }
+}
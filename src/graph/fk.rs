@@ -1,12 +1,14 @@
 use std::collections::HashMap;
 
+use log::debug;
 use pest::Parser;
-use pest::error::Error;
+use pest::error::{Error, ErrorVariant};
 use pest::iterators::{Pair, Pairs};
 use pest_derive::Parser;
+use petgraph::algo::has_path_connecting;
 
-use super::cfg::ControlFlowGraph;
 use super::ir;
+use crate::error::Span;
 
 #[derive(Debug)]
 pub struct Graph(pub Vec<Stmt>);
@@ -16,20 +18,15 @@ impl Graph {
         Self(stmts)
     }
 
-    pub fn to_ir(&self) -> ir::Graph {
-        ControlFlowGraph::from_graph(self).to_ir()
-    }
-
     pub fn from_ir(ir: &ir::Graph) -> Self {
         let mut conv = IrToFk::new();
-        println!("{:?}", &ir.0);
+        debug!("{:?}", &ir.0);
         conv.build(&ir.0);
         conv.finalize()
     }
 }
 
 struct Branch {
-    label: String,
     /// we know that we always have a `Par(Vec<Node>)` or `Seq(Vec<Node>)` node here
     stmts: ir::Node,
     target: String,
@@ -41,14 +38,24 @@ type Id = String;
 enum Ctx {
     Main,     // main path
     Deferred, // on branch path
+    Inline,   // on the first branch of a fork, which runs in place
 }
 
 struct IrToFk {
     dependencies: HashMap<Id, Vec<Id>>,
     main_path: Vec<Stmt>,
     branches: Vec<Branch>,
-    label_counter: usize,
     join_counter: usize,
+    /// Label of every loop with the joins in its body, whose counters have to
+    /// be set again on every iteration.
+    loops: Vec<(String, Vec<String>)>,
+    condition_counter: usize,
+    /// Dependencies the nesting already orders, as `(dependency, task)`,
+    /// which need no `fork`/`goto` after the dependency on the main path.
+    implied: Vec<(Id, Id)>,
+    /// Label where the alternatives of every choice meet again, with the
+    /// statement it goes on and how many alternatives jump there.
+    merges: Vec<(String, usize, usize)>,
 }
 
 impl IrToFk {
@@ -57,19 +64,15 @@ impl IrToFk {
             dependencies: HashMap::new(),
             main_path: Vec::new(),
             branches: Vec::new(),
-            label_counter: 0,
             join_counter: 1,
+            loops: Vec::new(),
+            condition_counter: 1,
+            implied: Vec::new(),
+            merges: Vec::new(),
         }
     }
 
-    fn new_label(&mut self) -> String {
-        let label = format!("L{}", self.label_counter);
-        self.label_counter += 1;
-        label
-    }
-
     fn finalize(mut self) -> Graph {
-        let branches = std::mem::take(&mut self.branches);
         self.main_path.push(Stmt::new(
             None,
             Node::Goto {
@@ -78,21 +81,113 @@ impl IrToFk {
         ));
         self.main_path
             .push(Stmt::new(Some("_end".to_string()), Node::Final));
-        for branch in branches {
-            self.expand_branch(branch.stmts, branch.target);
+        // Branches can defer branches of their own.
+        while !self.branches.is_empty() {
+            for branch in std::mem::take(&mut self.branches) {
+                self.expand_branch(branch.stmts, branch.target);
+            }
+        }
+
+        let exclusive = self.place_merges();
+        let mut arities = join_arities(&self.main_path);
+        for (id, count) in &mut arities {
+            // Only one of the alternatives arrives.
+            for (_, jumps) in exclusive.iter().filter(|(join, _)| join == id) {
+                debug_assert!(
+                    *count >= *jumps,
+                    "every alternative jumping to {id} is counted as arriving there"
+                );
+                *count -= jumps;
+            }
         }
+
+        let (looped, counters): (Vec<_>, Vec<_>) = arities
+            .into_iter()
+            .partition(|(id, _)| self.loops.iter().any(|(_, joins)| joins.contains(id)));
+
+        for (header, joins) in std::mem::take(&mut self.loops) {
+            let mut loop_counters: Vec<_> = looped
+                .iter()
+                .filter(|(id, _)| joins.contains(id))
+                .map(|(id, count)| {
+                    Stmt::new(
+                        None,
+                        Node::Counter {
+                            id: id.clone(),
+                            count: *count,
+                        },
+                    )
+                })
+                .collect();
+            let at = self
+                .main_path
+                .iter()
+                .position(|stmt| stmt.label.as_ref() == Some(&header));
+            if let (Some(at), Some(first)) = (at, loop_counters.first_mut()) {
+                // Jumping back now sets the counters before running the body.
+                first.label = self.main_path[at].label.take();
+                self.main_path.splice(at..at, loop_counters);
+            }
+        }
+
+        let counters = counters
+            .into_iter()
+            .map(|(id, count)| Stmt::new(None, Node::Counter { id, count }));
+        self.main_path.splice(0..0, counters);
         Graph::new(self.main_path)
     }
 
+    /// Labels the statement after every choice, or points the alternatives to
+    /// the label it already has. Returns the joins that alternatives reach
+    /// directly, with how many of them jump there.
+    fn place_merges(&mut self) -> Vec<(String, usize)> {
+        let mut exclusive = vec![];
+        for (merge, at, jumps) in std::mem::take(&mut self.merges) {
+            let Some(stmt) = self.main_path.get_mut(at) else {
+                continue;
+            };
+            let Some(existing) = stmt.label.clone() else {
+                stmt.label = Some(merge);
+                continue;
+            };
+            if let Node::Join { id } = &stmt.node {
+                exclusive.push((id.clone(), jumps));
+            }
+            for stmt in &mut self.main_path {
+                if let Node::Goto { id } = &mut stmt.node
+                    && *id == merge
+                {
+                    *id = existing.clone();
+                }
+            }
+        }
+        exclusive
+    }
+
     fn build(&mut self, nodes: &[ir::Node]) {
-        self.fetch_dependencies(nodes);
+        self.fetch_dependencies(nodes, nodes);
+
+        let nesting = crate::validate::nesting_petgraph(nodes);
+        let index = |id: &str| nesting.node_indices().find(|&idx| nesting[idx] == id);
+        for (task, deps) in &self.dependencies {
+            for dep in deps {
+                if let (Some(from), Some(to)) = (index(dep), index(task))
+                    && has_path_connecting(&nesting, from, to, None)
+                {
+                    self.implied.push((dep.clone(), task.clone()));
+                }
+            }
+        }
+
         self.convert_nodes(nodes, Ctx::Main);
     }
 
-    fn fetch_dependencies(&mut self, nodes: &[ir::Node]) {
+    /// A dependency on a labelled block is one on each of its last tasks, so
+    /// they all reach the single join of the dependent task.
+    fn fetch_dependencies(&mut self, root: &[ir::Node], nodes: &[ir::Node]) {
         for node in nodes {
             match node {
-                ir::Node::Atomic(parent, deps, _) => {
+                ir::Node::Atomic(parent, deps, ..) => {
                     if deps.is_empty() {
                         continue;
                     }
@@ -104,11 +199,15 @@ impl IrToFk {
                         self.dependencies
                             .entry(parent.clone())
                             .or_default()
-                            .push(dep.id());
+                            .extend(ir::dependency_tasks(root, &dep.id()));
                     }
                 }
-                ir::Node::Seq(children) | ir::Node::Par(children) => {
-                    self.fetch_dependencies(children);
+                ir::Node::Seq(children)
+                | ir::Node::Par(children)
+                | ir::Node::Loop(children)
+                | ir::Node::Choice(children)
+                | ir::Node::Labelled(_, children, _) => {
+                    self.fetch_dependencies(root, children);
                 }
                 ir::Node::Dep(_) => {}
             }
@@ -132,15 +231,19 @@ impl IrToFk {
             let label = pending_label.take();
             match &nodes[idx] {
                 ir::Node::Par(branches) => {
-                    let next_node = nodes.get(idx + 1);
-                    let target = next_node
-                        .map(|n| format!("L{}", n.id()))
-                        .unwrap_or_else(|| "_end".to_string());
-                    let next_has_deps = next_node.map_or(false, |n| self.node_has_dependencies(n));
-                    let join_label = if next_node.is_some() && !next_has_deps {
-                        Some(target.clone())
-                    } else {
-                        None
+                    let (target, join_label) = match nodes.get(idx + 1) {
+                        // Nothing follows it here, so it joins on its own.
+                        None => {
+                            let join = format!("J{}", nodes[idx].id());
+                            (join.clone(), Some(join))
+                        }
+                        Some(next) if self.node_has_dependencies(next) => {
+                            (format!("L{}", next.id()), None)
+                        }
+                        Some(next) => {
+                            let target = format!("L{}", next.id());
+                            (target.clone(), Some(target))
+                        }
                     };
 
                     self.convert_parallel(branches, label, target, join_label);
@@ -159,29 +262,65 @@ impl IrToFk {
         current
     }
 
+    fn update_condition(&mut self) -> usize {
+        let current = self.condition_counter;
+        self.condition_counter += 1;
+        current
+    }
+
     fn convert_node(&mut self, node: &ir::Node, label: Option<String>, ctx: Ctx) {
         match node {
-            ir::Node::Atomic(name, _, _) => {
-                self.resolve_dependencies(name);
-                self.main_path
-                    .push(Stmt::new(label.clone(), Node::Atomic { id: name.clone() }));
+            ir::Node::Atomic(name, _, _, attrs) => {
+                // The join already took the label the task is reached by.
+                let joined = self.resolve_dependencies(name);
+                let label = label.filter(|label| !joined || *label != format!("L{name}"));
+                self.main_path.push(Stmt::new(
+                    label,
+                    Node::Atomic {
+                        id: name.clone(),
+                        duration: attrs.duration,
+                    },
+                ));
                 // I need to check some way if the node itself it's the last on the branch, so we can avoid writing `fork` and `goto` for it.
                 self.post_terminal_deps(name, ctx);
             }
-            ir::Node::Seq(children) => {
+            ir::Node::Seq(children) | ir::Node::Labelled(_, children, _) => {
                 self.convert_nodes_with_label(children, ctx, label);
             }
-            ir::Node::Par(branches) => {
-                let target = "_end".to_string();
-                self.convert_parallel(branches, label, target, None);
+            ir::Node::Par(_) => {
+                self.convert_nodes_with_label(std::slice::from_ref(node), ctx, label);
+            }
+            ir::Node::Loop(children) => {
+                let start = self.main_path.len();
+                self.convert_nodes_with_label(children, ctx, label);
+                // The closing `if` jumps back to the first statement of the
+                // body, and the loop ends by falling through it.
+                let header = self.main_path[start]
+                    .label
+                    .get_or_insert_with(|| format!("R{}", node.id()))
+                    .clone();
+                let joins = self.main_path[start..]
+                    .iter()
+                    .filter_map(|stmt| match &stmt.node {
+                        Node::Join { id } => Some(id.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                self.loops.push((header.clone(), joins));
+                let cond = format!("k{}", self.update_condition());
+                self.main_path
+                    .push(Stmt::new(None, Node::If { cond, id: header }));
+            }
+            ir::Node::Choice(alternatives) => {
+                self.convert_choice(alternatives, label, ctx);
             }
             // We now that the only way to have a `Dep` node is as a dependency of an `Atomic` node, and we are already handling that case by recursively converting the dependencies before the atomic node itself.
-            ir::Node::Dep(id) => {}
+            ir::Node::Dep(_) => {}
         }
     }
 
     fn post_terminal_deps(&mut self, parent: &String, ctx: Ctx) {
-        if ctx != Ctx::Main {
+        if ctx == Ctx::Deferred {
             return;
         }
         let mut dependencies = self
@@ -191,11 +330,27 @@ impl IrToFk {
             .map(|(k, _)| k.clone())
             .collect::<Vec<_>>();
         dependencies.sort();
+        // The thread gets to those on its own.
+        dependencies.retain(|dep| !self.implied.contains(&(parent.clone(), dep.clone())));
 
         if dependencies.is_empty() {
             return;
         }
 
+        // The thread still has to reach the join of its fork, so it only
+        // signals the join the dependent waits on.
+        if ctx == Ctx::Inline {
+            for dep in dependencies {
+                self.main_path.push(Stmt::new(
+                    None,
+                    Node::Fork {
+                        id: format!("L{dep}"),
+                    },
+                ));
+            }
+            return;
+        }
+
         let last = dependencies
             .pop()
             .expect("terminal node should have dependencies");
@@ -219,7 +374,8 @@ impl IrToFk {
     /// parent: L{parent}
     /// counter: c{counter}
     /// deps: Vec<Node>
-    fn resolve_dependencies(&mut self, parent: &String) {
+    /// Returns whether the task waits on a join.
+    fn resolve_dependencies(&mut self, parent: &String) -> bool {
         let id = format!("L{parent}");
         if let Some(deps) = self.dependencies.get(parent)
             && !deps.is_empty()
@@ -231,7 +387,9 @@ impl IrToFk {
                     id: counter.clone(),
                 },
             ));
+            return true;
         }
+        false
     }
 
     /// branches is the list of branches that we need to convert in parallel.
@@ -268,41 +426,109 @@ impl IrToFk {
         //              goto LF
         //
         // end
+        // Jumping to the block has to run its forks too.
+        let mut main_label = main_label;
         for fork in forks {
             self.main_path
-                .push(Stmt::new(None, Node::Fork { id: fork }));
+                .push(Stmt::new(main_label.take(), Node::Fork { id: fork }));
         }
 
         // We are going to take the first branch as the main. (the most-left branch will be the "main" path always).
         let main_branch = &branches[0];
-        let mut main_label = main_label;
         if main_label.is_some() && self.node_has_dependencies(main_branch) {
             main_label = None;
         }
-        self.convert_node(main_branch, main_label, Ctx::Deferred);
+        self.convert_node(main_branch, main_label, Ctx::Inline);
 
-        if let Some(join_label) = join_label {
+        let mut target = target;
+        if let Some(mut join_label) = join_label {
+            // A block first in this one and last in its own list already took
+            // the join named after their shared first task.
+            let taken = |label: &String| {
+                self.main_path
+                    .iter()
+                    .any(|stmt| stmt.label.as_ref() == Some(label))
+            };
+            if taken(&join_label) {
+                join_label = (2..)
+                    .map(|n| format!("J{n}{}", main_branch.id()))
+                    .find(|label| !taken(label))
+                    .unwrap();
+                target = join_label.clone();
+            }
             let counter = format!("c{}", self.update_counter());
             self.main_path
                 .push(Stmt::new(Some(join_label), Node::Join { id: counter }));
         }
 
         for branch in &branches[1..] {
-            let label = self.new_label();
             self.branches.push(Branch {
-                label,                 // L{unknown}
                 stmts: branch.clone(), // the entire node.
                 target: target.clone(),
             });
         }
     }
 
+    /// The first alternative runs in place, every other one is reached through
+    /// an `if` and deferred like a branch, jumping back to the statement that
+    /// follows the choice.
+    /// Example:
+    /// $a,(b|c),d$ then:
+    /// begin
+    ///  a
+    ///  if k1 goto Lc
+    ///  b
+    ///  Mb: d
+    ///  goto _end
+    ///  Lc: c
+    ///      goto Mb
+    /// end
+    fn convert_choice(&mut self, alternatives: &[ir::Node], label: Option<String>, ctx: Ctx) {
+        let Some((first, rest)) = alternatives.split_first() else {
+            return;
+        };
+
+        let merge = format!("M{}", first.id());
+        let mut label = label;
+        for alternative in rest {
+            let cond = format!("k{}", self.update_condition());
+            self.main_path.push(Stmt::new(
+                label.take(),
+                Node::If {
+                    cond,
+                    id: format!("L{}", alternative.id()),
+                },
+            ));
+            // A deferred parallel block is taken for its branches, so it
+            // goes in a sequence of its own.
+            let stmts = match alternative {
+                ir::Node::Par(_) => ir::Node::Seq(vec![alternative.clone()]),
+                _ => alternative.clone(),
+            };
+            self.branches.push(Branch {
+                stmts,
+                target: merge.clone(),
+            });
+        }
+
+        self.convert_node(first, label, ctx);
+        self.merges.push((merge, self.main_path.len(), rest.len()));
+    }
+
     fn expand_branch(&mut self, branch: ir::Node, target: String) {
         match branch {
-            ir::Node::Atomic(label, _, is_terminal) => {
-                let labeled = format!("L{label}");
-                self.main_path
-                    .push(Stmt::new(Some(labeled), Node::Atomic { id: label.clone() }));
+            ir::Node::Atomic(label, _, is_terminal, attrs) => {
+                // The fork of the block and those of its dependencies all
+                // arrive at the join, so the task runs once.
+                let joined = self.resolve_dependencies(&label);
+                let labeled = (!joined).then(|| format!("L{label}"));
+                self.main_path.push(Stmt::new(
+                    labeled,
+                    Node::Atomic {
+                        id: label.clone(),
+                        duration: attrs.duration,
+                    },
+                ));
                 let continue_to_target =
                     self.emit_branch_dependencies(&label, &target, is_terminal);
                 if continue_to_target {
@@ -310,18 +536,13 @@ impl IrToFk {
                         .push(Stmt::new(None, Node::Goto { id: target }));
                 }
             }
-            ir::Node::Par(branch) | ir::Node::Seq(branch) => {
+            ir::Node::Par(branch) | ir::Node::Seq(branch) | ir::Node::Labelled(_, branch, _) => {
                 let first_node = branch
                     .first()
                     .expect("Branch should have at least one node");
-                let label = first_node.id();
-                let labeled = format!("L{label}");
-                self.convert_node(first_node, Some(labeled), Ctx::Deferred);
-                let mut continue_to_target = self.emit_branch_dependencies(
-                    &label,
-                    &target,
-                    Self::is_terminal_node(first_node),
-                );
+                let labeled = format!("L{}", first_node.id());
+                let mut continue_to_target =
+                    self.convert_branch_node(first_node, Some(labeled), &target);
                 if !continue_to_target {
                     return;
                 }
@@ -344,13 +565,7 @@ impl IrToFk {
                     //
                     // end
                     // node.
-                    self.convert_node(node, None, Ctx::Deferred);
-                    let node_id = node.id();
-                    continue_to_target = self.emit_branch_dependencies(
-                        &node_id,
-                        &target,
-                        Self::is_terminal_node(node),
-                    );
+                    continue_to_target = self.convert_branch_node(node, None, &target);
                     if !continue_to_target {
                         return;
                     }
@@ -360,7 +575,31 @@ impl IrToFk {
                         .push(Stmt::new(None, Node::Goto { id: target }));
                 }
             }
-            _ => unreachable!(),
+            ir::Node::Loop(_) | ir::Node::Choice(_) => {
+                self.expand_branch(ir::Node::Seq(vec![branch]), target)
+            }
+            ir::Node::Dep(_) => unreachable!(),
+        }
+    }
+
+    /// Converts a node of a deferred branch, returning whether the branch goes
+    /// on after it. The tasks of a nested block fork to their dependents as
+    /// they run, a task on its own does it once it's in the branch.
+    fn convert_branch_node(
+        &mut self,
+        node: &ir::Node,
+        label: Option<String>,
+        target: &str,
+    ) -> bool {
+        match node {
+            ir::Node::Atomic(id, _, is_terminal, _) => {
+                self.convert_node(node, label, Ctx::Deferred);
+                self.emit_branch_dependencies(id, target, *is_terminal)
+            }
+            _ => {
+                self.convert_node(node, label, Ctx::Inline);
+                true
+            }
         }
     }
 
@@ -373,24 +612,18 @@ impl IrToFk {
             .collect::<Vec<_>>();
         dependencies.sort();
 
-        if dependencies.is_empty() {
-            return !is_terminal;
+        if dependencies.is_empty() && !is_terminal {
+            return true;
         }
 
         if is_terminal {
-            let last = dependencies
-                .pop()
-                .expect("terminal node should have dependencies");
-
             for dep in dependencies {
                 let label = format!("L{dep}");
                 self.main_path
                     .push(Stmt::new(None, Node::Fork { id: label }));
             }
 
-            let label = format!("L{last}");
-            self.main_path
-                .push(Stmt::new(None, Node::Goto { id: label }));
+            self.main_path.push(Stmt::new(None, Node::Quit));
             return false;
         }
 
@@ -412,31 +645,91 @@ impl IrToFk {
             .map(|deps| !deps.is_empty())
             .unwrap_or(false)
     }
+}
+
+/// Counts how many threads arrive at every join: one for each `fork`/`goto` to
+/// its label plus the thread that falls through from the previous statement.
+pub(crate) fn join_arities(stmts: &[Stmt]) -> Vec<(String, usize)> {
+    let mut arities = vec![];
+
+    for (idx, stmt) in stmts.iter().enumerate() {
+        let Node::Join { id } = &stmt.node else {
+            continue;
+        };
 
-    fn is_terminal_node(node: &ir::Node) -> bool {
-        matches!(node, ir::Node::Atomic(_, _, true))
+        let mut count = stmts
+            .iter()
+            .filter(|other| match (&other.node, &stmt.label) {
+                (Node::Fork { id: target } | Node::Goto { id: target }, Some(label)) => {
+                    target == label
+                }
+                _ => false,
+            })
+            .count();
+
+        let falls_through = idx.checked_sub(1).is_some_and(|prev| {
+            !matches!(
+                stmts[prev].node,
+                Node::Goto { .. } | Node::Quit | Node::Final
+            )
+        });
+        if falls_through {
+            count += 1;
+        }
+
+        arities.push((id.clone(), count));
     }
+
+    arities
 }
 
 #[derive(Debug)]
 pub struct Stmt {
     pub label: Option<String>,
     pub node: Node,
+    /// Where the statement (label included) was written, for diagnostics.
+    pub span: Option<Span>,
 }
 
 impl Stmt {
     pub fn new(label: Option<String>, node: Node) -> Self {
-        Self { label, node }
+        Self {
+            label,
+            node,
+            span: None,
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum Node {
     Final,
-    Join { id: String },
-    Goto { id: String },
-    Fork { id: String },
-    Atomic { id: String },
+    Join {
+        id: String,
+    },
+    /// Initialization of a join counter: `c1 := 3`.
+    Counter {
+        id: String,
+        count: usize,
+    },
+    Goto {
+        id: String,
+    },
+    /// Terminates the current thread.
+    Quit,
+    Fork {
+        id: String,
+    },
+    /// Jumps to `id` when `cond` holds and falls through otherwise. Conditions
+    /// only name the alternative, they are never evaluated.
+    If {
+        cond: String,
+        id: String,
+    },
+    Atomic {
+        id: String,
+        duration: Option<u64>,
+    },
 }
 
 #[derive(Parser)]
@@ -450,47 +743,88 @@ pub fn parse(input: impl AsRef<str>) -> Result<Graph, Error<Rule>> {
         .unwrap();
 
     let mut stmts = vec![];
-    parse_statements(rule.into_inner(), &mut stmts);
+    parse_statements(rule.into_inner(), &mut stmts)?;
 
     Ok(Graph::new(stmts))
 }
 
-fn parse_statements(pairs: Pairs<Rule>, stmts: &mut Vec<Stmt>) {
+#[allow(clippy::result_large_err)]
+fn parse_statements(pairs: Pairs<Rule>, stmts: &mut Vec<Stmt>) -> Result<(), Error<Rule>> {
     for pair in pairs {
         let Some(inner) = pair.into_inner().next() else {
             break;
         };
 
         match inner.as_rule() {
-            Rule::LabeledStatement => parse_labeled(inner, stmts),
-            Rule::UnlabeledStatement => parse_unlabeled(inner, stmts),
+            Rule::LabeledStatement => parse_labeled(inner, stmts)?,
+            Rule::UnlabeledStatement => parse_unlabeled(inner, stmts)?,
             _ => break,
         }
     }
+    Ok(())
 }
 
-fn parse_labeled(pair: Pair<Rule>, stmts: &mut Vec<Stmt>) {
+#[allow(clippy::result_large_err)]
+fn parse_labeled(pair: Pair<Rule>, stmts: &mut Vec<Stmt>) -> Result<(), Error<Rule>> {
+    let span = stmt_span(&pair);
     let mut inner = pair.into_inner();
     let label_pair = inner.next().unwrap();
     let label = label_pair.into_inner().next().unwrap().as_str().to_string();
 
     let node_pair = inner.next().unwrap().into_inner().next().unwrap();
-    let node = parse_node(node_pair);
+    let node = parse_node(node_pair)?;
 
-    stmts.push(Stmt::new(Some(label), node));
+    stmts.push(Stmt {
+        span: Some(span),
+        ..Stmt::new(Some(label), node)
+    });
+    Ok(())
 }
 
-fn parse_unlabeled(pair: Pair<Rule>, stmts: &mut Vec<Stmt>) {
+#[allow(clippy::result_large_err)]
+fn parse_unlabeled(pair: Pair<Rule>, stmts: &mut Vec<Stmt>) -> Result<(), Error<Rule>> {
+    let span = stmt_span(&pair);
     let inner = pair.into_inner().next().unwrap();
-    let node = parse_node(inner);
-    stmts.push(Stmt::new(None, node));
+    let node = parse_node(inner)?;
+    stmts.push(Stmt {
+        span: Some(span),
+        ..Stmt::new(None, node)
+    });
+    Ok(())
 }
 
-fn parse_node(pair: Pair<Rule>) -> Node {
-    match pair.as_rule() {
+/// Span of the statement without the newlines that end it.
+fn stmt_span(pair: &Pair<Rule>) -> Span {
+    let start = pair.as_span().start();
+    start..start + pair.as_str().trim_end().len()
+}
+
+/// Error for a number that doesn't fit in its type.
+fn too_large(pair: &Pair<Rule>, what: &str) -> Error<Rule> {
+    Error::new_from_span(
+        ErrorVariant::CustomError {
+            message: format!("{what} {} is too large", pair.as_str()),
+        },
+        pair.as_span(),
+    )
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_node(pair: Pair<Rule>) -> Result<Node, Error<Rule>> {
+    let node = match pair.as_rule() {
         Rule::Task => {
-            let id = pair.into_inner().next().unwrap().as_str().to_string();
-            Node::Atomic { id }
+            let mut inner = pair.into_inner();
+            let id = inner.next().unwrap().as_str().to_string();
+            let duration = inner
+                .next()
+                .map(|count| {
+                    count
+                        .as_str()
+                        .parse()
+                        .map_err(|_| too_large(&count, "duration"))
+                })
+                .transpose()?;
+            Node::Atomic { id, duration }
         }
         Rule::Fork => {
             let id = pair.into_inner().next().unwrap().as_str().to_string();
@@ -500,10 +834,103 @@ fn parse_node(pair: Pair<Rule>) -> Node {
             let id = pair.into_inner().next().unwrap().as_str().to_string();
             Node::Goto { id }
         }
+        Rule::If => {
+            let mut inner = pair.into_inner();
+            let cond = inner.next().unwrap().as_str().to_string();
+            let id = inner.next().unwrap().as_str().to_string();
+            Node::If { cond, id }
+        }
+        Rule::Quit => Node::Quit,
         Rule::Join => {
             let id = pair.into_inner().next().unwrap().as_str().to_string();
             Node::Join { id }
         }
+        Rule::Counter => {
+            let mut inner = pair.into_inner();
+            let id = inner.next().unwrap().as_str().to_string();
+            let count_pair = inner.next().unwrap();
+            let count = count_pair
+                .as_str()
+                .parse()
+                .map_err(|_| too_large(&count_pair, "counter"))?;
+            Node::Counter { id, count }
+        }
         _ => unreachable!(),
+    };
+    Ok(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_too_long_duration_is_a_syntax_error() {
+        let error = parse("begin\ns0:99999999999999999999999\nend").unwrap_err();
+
+        assert!(error.variant.message().contains("too large"));
+    }
+
+    #[test]
+    fn test_counter_declaration() {
+        let graph = parse("begin\nc1 := 3\ns1\nend").unwrap();
+
+        assert!(matches!(
+            &graph.0[0].node,
+            Node::Counter { id, count: 3 } if id == "c1"
+        ));
+        let error = parse("begin\nc1 := 99999999999999999999999\nend").unwrap_err();
+        assert!(error.variant.message().contains("counter"));
+    }
+
+    #[test]
+    fn test_join_arities() {
+        let graph = parse(
+            "begin\nc1 := 2\nc2 := 3\nfork L2\ns1\ngoto J1\nL2: s2\nJ1: join c1\n\
+             fork L3\nfork L4\ns3\ngoto J2\nL3: s4\ngoto J2\nL4: s5\ngoto J2\nJ2: join c2\n\
+             goto _end\n_end: end\nend",
+        )
+        .unwrap();
+
+        // `J1` is reached by a `goto` and by falling through from `s2`, `J2`
+        // only by the three `goto`s.
+        assert_eq!(
+            join_arities(&graph.0),
+            vec![("c1".to_string(), 2), ("c2".to_string(), 3)]
+        );
+    }
+
+    #[test]
+    fn test_quit_is_parsed() {
+        let graph = parse("begin\ns1\nquit\nend").unwrap();
+
+        assert!(matches!(graph.0[1].node, Node::Quit));
+    }
+
+    #[test]
+    fn test_terminal_branch_quits() {
+        let nodes = ir::parse("$s0,{s3,[s1,s2!]},s4$").unwrap().0;
+        let graph = Graph::from_ir(&ir::Graph::new(nodes));
+
+        // The forked branch ends after `s2` instead of going to the join.
+        let s2 = graph
+            .0
+            .iter()
+            .position(|stmt| matches!(&stmt.node, Node::Atomic { id, .. } if id == "s2"))
+            .unwrap();
+        assert!(matches!(graph.0[s2 + 1].node, Node::Quit));
+        assert_eq!(join_arities(&graph.0), vec![("c1".to_string(), 1)]);
+    }
+
+    #[test]
+    fn test_forked_dependent_runs_once() {
+        let nodes = ir::parse("$s0,{s1,s2#{s1}},s3$").unwrap().0;
+        let graph = Graph::from_ir(&ir::Graph::new(nodes));
+
+        // `s1` only signals the join `s2` waits on instead of forking it again.
+        let simulation =
+            crate::graph::Graph::<Stmt, crate::graph::ForkJoin>::new(graph.0).simulate();
+        assert!(simulation.is_ok());
+        assert_eq!(simulation.trace, vec!["s0", "s1", "s2", "s3"]);
     }
 }
@@ -1,9 +1,14 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
 use pest::Parser;
-use pest::error::Error;
+use pest::error::{Error, ErrorVariant, LineColLocation};
 use pest::iterators::{Pair, Pairs};
 use pest_derive::Parser;
 
-use super::{fk, par};
+use super::{dag, fk, par, sem, span_of};
+use crate::ValidationError;
+use crate::error::Span;
 
 #[derive(Debug)]
 pub struct Graph(pub Vec<Node>);
@@ -20,28 +25,192 @@ impl Graph {
     pub fn to_par(&self) -> par::Graph {
         par::Graph::from_ir(self)
     }
+
+    pub fn to_sem(&self) -> Result<sem::Graph, ValidationError> {
+        sem::Graph::from_ir(self)
+    }
+
+    /// Rebuilds the most structured graph for a plain precedence DAG.
+    pub fn from_petgraph<E>(graph: &petgraph::Graph<String, E>) -> Self {
+        Graph::new(dag::Dag::from_petgraph(graph).to_ir())
+    }
+
+    /// Reads the tasks as a sequential program and rebuilds it with all the
+    /// parallelism their read/write sets allow.
+    pub fn parallelize(&self) -> Graph {
+        let mut tasks = vec![];
+        collect_tasks(&self.0, &mut tasks);
+        Graph::new(dag::Dag::from_accesses(tasks).to_ir())
+    }
+}
+
+fn collect_tasks(nodes: &[Node], tasks: &mut Vec<(String, Attrs)>) {
+    for node in nodes {
+        match node {
+            Node::Atomic(id, _, _, attrs) => tasks.push((id.clone(), attrs.clone())),
+            Node::Par(children)
+            | Node::Seq(children)
+            | Node::Loop(children)
+            | Node::Choice(children)
+            | Node::Labelled(_, children, _) => {
+                collect_tasks(children, tasks);
+            }
+            Node::Dep(_) => {}
+        }
+    }
+}
+
+/// Tasks a `#{dep}` waits for: the task itself, or the last tasks of the
+/// block labelled `@dep`.
+pub(super) fn dependency_tasks(nodes: &[Node], dep: &str) -> Vec<String> {
+    match dep.strip_prefix('@') {
+        Some(label) => find_labelled(nodes, label)
+            .map(last_tasks)
+            .unwrap_or_default(),
+        None => vec![dep.to_string()],
+    }
+}
+
+fn find_labelled<'a>(nodes: &'a [Node], label: &str) -> Option<&'a Node> {
+    nodes.iter().find_map(|node| match node {
+        Node::Labelled(name, ..) if name == label => Some(node),
+        Node::Par(children)
+        | Node::Seq(children)
+        | Node::Loop(children)
+        | Node::Choice(children)
+        | Node::Labelled(_, children, _) => find_labelled(children, label),
+        Node::Atomic(..) | Node::Dep(_) => None,
+    })
+}
+
+/// Whether any of the nodes repeats.
+pub(super) fn has_loops(nodes: &[Node]) -> bool {
+    nodes.iter().any(|node| match node {
+        Node::Loop(_) => true,
+        Node::Par(children)
+        | Node::Seq(children)
+        | Node::Choice(children)
+        | Node::Labelled(_, children, _) => has_loops(children),
+        Node::Atomic(..) | Node::Dep(_) => false,
+    })
+}
+
+/// Whether any of the nodes picks between alternatives.
+pub(super) fn has_choices(nodes: &[Node]) -> bool {
+    nodes.iter().any(|node| match node {
+        Node::Choice(_) => true,
+        Node::Par(children)
+        | Node::Seq(children)
+        | Node::Loop(children)
+        | Node::Labelled(_, children, _) => has_choices(children),
+        Node::Atomic(..) | Node::Dep(_) => false,
+    })
+}
+
+/// Every way the choices can go: the same nodes with each choice replaced by
+/// one of its alternatives. Without choices, that's the nodes themselves.
+pub fn resolutions(nodes: &[Node]) -> Vec<Vec<Node>> {
+    nodes.iter().fold(vec![vec![]], |resolved, node| {
+        let options = resolve(node);
+        resolved
+            .iter()
+            .flat_map(|prefix| {
+                options.iter().map(move |option| {
+                    let mut nodes = prefix.clone();
+                    nodes.push(option.clone());
+                    nodes
+                })
+            })
+            .collect()
+    })
+}
+
+fn resolve(node: &Node) -> Vec<Node> {
+    match node {
+        Node::Choice(alternatives) => alternatives.iter().flat_map(resolve).collect(),
+        Node::Seq(children) => resolutions(children).into_iter().map(Node::Seq).collect(),
+        Node::Par(children) => resolutions(children).into_iter().map(Node::Par).collect(),
+        Node::Loop(children) => resolutions(children).into_iter().map(Node::Loop).collect(),
+        Node::Labelled(label, children, span) => resolutions(children)
+            .into_iter()
+            .map(|children| Node::Labelled(label.clone(), children, span.clone()))
+            .collect(),
+        Node::Atomic(..) | Node::Dep(_) => vec![node.clone()],
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Node {
     Par(Vec<Node>),
     Seq(Vec<Node>),
-    Atomic(String, Vec<Node>, bool),
+    Atomic(String, Vec<Node>, bool, Attrs),
     Dep(String),
+    /// A sequence that runs one or more times.
+    Loop(Vec<Node>),
+    /// Alternatives of which exactly one runs.
+    Choice(Vec<Node>),
+    /// A block named with `@label`, which `#{@label}` waits for as a whole.
+    /// Its children run in sequence, like a [`Node::Seq`]. The span is the one
+    /// of the label, for diagnostics.
+    Labelled(String, Vec<Node>, Option<Span>),
+}
+
+/// Annotations attached to a task.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attrs {
+    /// Variables the task reads: `r:a,b`.
+    pub reads: Vec<String>,
+    /// Variables the task writes: `w:c`.
+    pub writes: Vec<String>,
+    /// How long the task takes: `s1:5`.
+    pub duration: Option<u64>,
+    /// Where the task identifier was written, for diagnostics.
+    pub span: Option<Span>,
+    /// Where every `#{}` dependency was written, in the same order.
+    pub dep_spans: Vec<Span>,
+}
+
+impl Attrs {
+    pub fn has_access(&self) -> bool {
+        !self.reads.is_empty() || !self.writes.is_empty()
+    }
+
+    /// Variables that break Bernstein's conditions: `R(a) ∩ W(b)`, `W(a) ∩ R(b)`
+    /// and `W(a) ∩ W(b)` must all be empty for both tasks to run concurrently.
+    pub fn conflicts_with(&self, other: &Attrs) -> Vec<String> {
+        let mut conflicts: Vec<String> = self
+            .writes
+            .iter()
+            .filter(|var| other.reads.contains(var) || other.writes.contains(var))
+            .chain(self.reads.iter().filter(|var| other.writes.contains(var)))
+            .cloned()
+            .collect();
+        conflicts.sort();
+        conflicts.dedup();
+        conflicts
+    }
 }
 
 impl Node {
     pub fn id(&self) -> String {
         match self {
-            Node::Atomic(id, _, _) | Node::Dep(id) => id.clone(),
-            Node::Par(b) | Node::Seq(b) => b.first().map(Node::id).unwrap(),
+            Node::Atomic(id, ..) | Node::Dep(id) => id.clone(),
+            Node::Par(b)
+            | Node::Seq(b)
+            | Node::Loop(b)
+            | Node::Choice(b)
+            | Node::Labelled(_, b, _) => b.first().map(Node::id).unwrap(),
         }
     }
 
     pub fn last_node(&self) -> Option<&Node> {
         match self {
-            Node::Par(b) | Node::Seq(b) => b.last().map(|n| n.last_node()).flatten(),
-            Node::Atomic(_, _, _) => Some(self),
+            Node::Par(b)
+            | Node::Seq(b)
+            | Node::Loop(b)
+            | Node::Choice(b)
+            | Node::Labelled(_, b, _) => b.last().and_then(|n| n.last_node()),
+            Node::Atomic(..) => Some(self),
             _ => None, // We don't want to catch the last dep node.
         }
     }
@@ -53,54 +222,445 @@ struct IrParser;
 
 #[allow(clippy::result_large_err)]
 pub fn parse(input: impl AsRef<str>) -> Result<Graph, Error<Rule>> {
+    parse_in(input, Path::new(""))
+}
+
+/// Like [`parse`], looking up the files the program includes from `dir`.
+#[allow(clippy::result_large_err)]
+pub fn parse_in(input: impl AsRef<str>, dir: &Path) -> Result<Graph, Error<Rule>> {
     let rule = IrParser::parse(Rule::Program, input.as_ref())?
         .next()
         .unwrap();
 
+    let mut blocks = Blocks {
+        dir: dir.to_path_buf(),
+        ..Blocks::default()
+    };
+    let nodes = parse_program(rule, &mut blocks)?;
+    Ok(Graph::new(nodes))
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_program(rule: Pair<Rule>, blocks: &mut Blocks) -> Result<Vec<Node>, Error<Rule>> {
     let mut nodes = vec![];
-    let inner = rule.into_inner().next().unwrap().into_inner();
-    parse_nodes(inner, &mut nodes);
+    for pair in rule.into_inner() {
+        match pair.as_rule() {
+            Rule::Include => blocks.include(pair)?,
+            Rule::Definition => blocks.define(pair)?,
+            Rule::EOI => {}
+            _ => parse_nodes(pair.into_inner(), &mut nodes, blocks)?,
+        }
+    }
+    blocks.resolve_dependencies(&mut nodes);
+    Ok(nodes)
+}
 
-    Ok(Graph::new(nodes))
+/// Blocks defined with `let`, copied in place of every reference to them.
+#[derive(Default)]
+struct Blocks {
+    definitions: HashMap<String, Node>,
+    /// How many times every block was expanded so far.
+    expansions: HashMap<String, usize>,
+    /// Last tasks of every copy of a block, which `#{block}` waits for.
+    last_tasks: HashMap<String, Vec<String>>,
+    /// Tasks of every copy so far.
+    expanded: HashSet<String>,
+    /// Directory the included files are relative to.
+    dir: PathBuf,
+    /// Files being included, outermost first, to catch the ones that loop.
+    including: Vec<PathBuf>,
 }
 
-fn parse_nodes(pairs: Pairs<Rule>, nodes: &mut Vec<Node>) {
-    for pair in pairs {
-        let inner = pair.into_inner().next().unwrap();
-        match inner.as_rule() {
-            Rule::Task => nodes.push(parse_task(inner)),
-            Rule::Sequencial => {
-                let mut children = vec![];
-                parse_nodes(
-                    inner.into_inner().next().unwrap().into_inner(),
-                    &mut children,
-                );
-                nodes.push(Node::Seq(children));
+impl Blocks {
+    /// Reads an included file and defines its blocks under its name.
+    #[allow(clippy::result_large_err)]
+    fn include(&mut self, pair: Pair<Rule>) -> Result<(), Error<Rule>> {
+        let mut inner = pair.into_inner();
+        let path_pair = inner.next().unwrap();
+        let file = self.dir.join(path_pair.clone().into_inner().as_str());
+        let name = match inner.next() {
+            Some(alias) => alias.as_str().to_string(),
+            None => file
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(custom_error(
+                &path_pair,
+                format!("'{name}' can't name a module, add `as name` to the include"),
+            ));
+        }
+
+        let path = file.canonicalize().map_err(|e| {
+            custom_error(&path_pair, format!("can't read '{}': {e}", file.display()))
+        })?;
+        if let Some(start) = self.including.iter().position(|other| *other == path) {
+            let chain = self.including[start..]
+                .iter()
+                .chain(std::iter::once(&path))
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            return Err(custom_error(&path_pair, format!("include cycle: {chain}")));
+        }
+        let text = std::fs::read_to_string(&path).map_err(|e| {
+            custom_error(&path_pair, format!("can't read '{}': {e}", file.display()))
+        })?;
+
+        let mut module = Blocks {
+            dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            including: self.including.iter().cloned().chain([path]).collect(),
+            ..Blocks::default()
+        };
+        let program = IrParser::parse(Rule::Module, &text)
+            .and_then(|mut pairs| parse_program(pairs.next().unwrap(), &mut module))
+            .map_err(|error| {
+                let (line, col) = match error.line_col {
+                    LineColLocation::Pos(at) | LineColLocation::Span(at, _) => at,
+                };
+                let message = error.variant.message();
+                custom_error(
+                    &path_pair,
+                    format!("in '{}' at {line}:{col}: {message}", file.display()),
+                )
+            })?;
+
+        let mut imported: Vec<_> = module
+            .definitions
+            .into_iter()
+            .map(|(block, node)| (format!("{name}.{block}"), node))
+            .collect();
+        if !program.is_empty() {
+            imported.push((name.clone(), Node::Seq(program)));
+        }
+        for (block, mut node) in imported {
+            if self.definitions.contains_key(&block) {
+                return Err(custom_error(
+                    &path_pair,
+                    format!("block '{block}' is already defined"),
+                ));
+            }
+            // Its spans point into another file.
+            clear_spans(std::slice::from_mut(&mut node));
+            self.definitions.insert(block, node);
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn define(&mut self, pair: Pair<Rule>) -> Result<(), Error<Rule>> {
+        let mut inner = pair.into_inner();
+        let name_pair = inner.next().unwrap();
+        let name = name_pair.as_str().to_string();
+        if self.definitions.contains_key(&name) {
+            return Err(custom_error(
+                &name_pair,
+                format!("block '{name}' is already defined"),
+            ));
+        }
+
+        // The blocks used inside are numbered and waited for on their own.
+        let expansions = std::mem::take(&mut self.expansions);
+        let last_tasks = std::mem::take(&mut self.last_tasks);
+        let expanded = std::mem::take(&mut self.expanded);
+        let mut body = vec![];
+        let parsed = parse_nodes(inner, &mut body, self);
+        self.resolve_dependencies(&mut body);
+        self.expansions = expansions;
+        self.last_tasks = last_tasks;
+        self.expanded = expanded;
+        parsed?;
+
+        self.definitions.insert(name, body.remove(0));
+        Ok(())
+    }
+
+    /// A fresh copy of the block, its tasks renamed to `name.id` the first
+    /// time and `name#n.id` the n-th one.
+    fn expand(&mut self, name: &str) -> Node {
+        let body = &self.definitions[name];
+        let mut tasks = vec![];
+        collect_tasks(std::slice::from_ref(body), &mut tasks);
+        let mut own: HashSet<_> = tasks.into_iter().map(|(id, _)| id).collect();
+        collect_labels(std::slice::from_ref(body), &mut own);
+
+        let node = loop {
+            let count = self.expansions.entry(name.to_string()).or_default();
+            *count += 1;
+            let prefix = match *count {
+                1 => format!("{name}."),
+                n => format!("{name}#{n}."),
+            };
+            let node = rename(body, &prefix, &own);
+
+            // The copy of an included program may already use these names.
+            let mut renamed = vec![];
+            collect_tasks(std::slice::from_ref(&node), &mut renamed);
+            if renamed.iter().all(|(id, _)| !self.expanded.contains(id)) {
+                self.expanded.extend(renamed.into_iter().map(|(id, _)| id));
+                break node;
+            }
+        };
+
+        self.last_tasks
+            .entry(name.to_string())
+            .or_default()
+            .extend(last_tasks(&node));
+        node
+    }
+
+    /// Replaces every dependency on a block by its last tasks.
+    fn resolve_dependencies(&self, nodes: &mut [Node]) {
+        for node in nodes {
+            match node {
+                Node::Atomic(_, deps, _, attrs) => {
+                    let mut resolved = vec![];
+                    let mut spans = vec![];
+                    for (i, dep) in deps.drain(..).enumerate() {
+                        let span = attrs.dep_spans.get(i).cloned();
+                        let tasks = match &dep {
+                            Node::Dep(id) => self.last_tasks.get(id),
+                            _ => None,
+                        };
+                        match tasks {
+                            Some(tasks) => {
+                                resolved.extend(tasks.iter().cloned().map(Node::Dep));
+                                spans.extend(tasks.iter().filter_map(|_| span.clone()));
+                            }
+                            None => {
+                                resolved.push(dep);
+                                spans.extend(span);
+                            }
+                        }
+                    }
+                    *deps = resolved;
+                    attrs.dep_spans = spans;
+                }
+                Node::Par(children)
+                | Node::Seq(children)
+                | Node::Loop(children)
+                | Node::Choice(children)
+                | Node::Labelled(_, children, _) => self.resolve_dependencies(children),
+                Node::Dep(_) => {}
             }
-            Rule::Parallel => {
-                let mut children = vec![];
-                parse_nodes(
-                    inner.into_inner().next().unwrap().into_inner(),
-                    &mut children,
-                );
-                nodes.push(Node::Par(children));
+        }
+    }
+}
+
+/// Adds the labels of the blocks in `nodes` to `labels`, as `#{}` names them.
+fn collect_labels(nodes: &[Node], labels: &mut HashSet<String>) {
+    for node in nodes {
+        match node {
+            Node::Labelled(label, children, _) => {
+                labels.insert(format!("@{label}"));
+                collect_labels(children, labels);
+            }
+            Node::Par(children)
+            | Node::Seq(children)
+            | Node::Loop(children)
+            | Node::Choice(children) => {
+                collect_labels(children, labels);
             }
-            _ => unreachable!(),
+            Node::Atomic(..) | Node::Dep(_) => {}
+        }
+    }
+}
+
+fn clear_spans(nodes: &mut [Node]) {
+    for node in nodes {
+        match node {
+            Node::Atomic(_, _, _, attrs) => {
+                attrs.span = None;
+                attrs.dep_spans.clear();
+            }
+            Node::Labelled(_, children, span) => {
+                *span = None;
+                clear_spans(children);
+            }
+            Node::Par(children)
+            | Node::Seq(children)
+            | Node::Loop(children)
+            | Node::Choice(children) => clear_spans(children),
+            Node::Dep(_) => {}
+        }
+    }
+}
+
+/// Prefixes the tasks and labels in `own`, and the dependencies on them, with
+/// `prefix`.
+fn rename(node: &Node, prefix: &str, own: &HashSet<String>) -> Node {
+    let renamed = |id: &String| match id.strip_prefix('@') {
+        Some(label) if own.contains(id) => format!("@{prefix}{label}"),
+        _ if own.contains(id) => format!("{prefix}{id}"),
+        _ => id.clone(),
+    };
+    let rename_all = |children: &[Node]| {
+        children
+            .iter()
+            .map(|child| rename(child, prefix, own))
+            .collect()
+    };
+
+    match node {
+        Node::Atomic(id, deps, terminal, attrs) => {
+            Node::Atomic(renamed(id), rename_all(deps), *terminal, attrs.clone())
+        }
+        Node::Dep(id) => Node::Dep(renamed(id)),
+        Node::Par(children) => Node::Par(rename_all(children)),
+        Node::Seq(children) => Node::Seq(rename_all(children)),
+        Node::Loop(children) => Node::Loop(rename_all(children)),
+        Node::Choice(children) => Node::Choice(rename_all(children)),
+        Node::Labelled(label, children, span) => Node::Labelled(
+            format!("{prefix}{label}"),
+            rename_all(children),
+            span.clone(),
+        ),
+    }
+}
+
+/// Tasks that finish a node, the ones whatever follows it waits for.
+pub(super) fn last_tasks(node: &Node) -> Vec<String> {
+    match node {
+        Node::Atomic(id, _, false, _) => vec![id.clone()],
+        Node::Atomic(..) | Node::Dep(_) => vec![],
+        Node::Seq(children) | Node::Loop(children) | Node::Labelled(_, children, _) => {
+            children.last().map(last_tasks).unwrap_or_default()
+        }
+        Node::Par(children) | Node::Choice(children) => {
+            children.iter().flat_map(last_tasks).collect()
         }
     }
 }
 
-fn parse_task(pair: Pair<Rule>) -> Node {
+#[allow(clippy::result_large_err)]
+fn custom_error(pair: &Pair<Rule>, message: String) -> Error<Rule> {
+    Error::new_from_span(ErrorVariant::CustomError { message }, pair.as_span())
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_nodes(
+    pairs: Pairs<Rule>,
+    nodes: &mut Vec<Node>,
+    blocks: &mut Blocks,
+) -> Result<(), Error<Rule>> {
+    for pair in pairs {
+        let mut pairs = pair.into_inner();
+        let node = parse_node(pairs.next().unwrap(), blocks)?;
+
+        let repeated = pairs.next().is_some_and(|p| p.as_rule() == Rule::Repeat);
+        nodes.push(match node {
+            Node::Seq(children) if repeated => Node::Loop(children),
+            node if repeated => Node::Loop(vec![node]),
+            node => node,
+        });
+    }
+    Ok(())
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_node(pair: Pair<Rule>, blocks: &mut Blocks) -> Result<Node, Error<Rule>> {
+    let node = match pair.as_rule() {
+        Rule::Task => parse_task_or_block(pair, blocks)?,
+        Rule::Labelled => {
+            let start = pair.as_span().start();
+            let mut inner = pair.into_inner();
+            let label_pair = inner.next().unwrap();
+            let label = label_pair.as_str().to_string();
+            // From the `@` to the end of the name.
+            let span = start..label_pair.as_span().end();
+            let block = parse_node(inner.next().unwrap(), blocks)?;
+            Node::Labelled(label, vec![block], Some(span))
+        }
+        Rule::Sequencial => {
+            let mut children = vec![];
+            parse_nodes(
+                pair.into_inner().next().unwrap().into_inner(),
+                &mut children,
+                blocks,
+            )?;
+            Node::Seq(children)
+        }
+        Rule::Parallel => {
+            let mut children = vec![];
+            parse_nodes(
+                pair.into_inner().next().unwrap().into_inner(),
+                &mut children,
+                blocks,
+            )?;
+            Node::Par(children)
+        }
+        Rule::Choice => {
+            let mut alternatives = vec![];
+            parse_nodes(pair.into_inner(), &mut alternatives, blocks)?;
+            Node::Choice(alternatives)
+        }
+        _ => unreachable!(),
+    };
+    Ok(node)
+}
+
+/// A task, or a copy of the block it names.
+#[allow(clippy::result_large_err)]
+fn parse_task_or_block(pair: Pair<Rule>, blocks: &mut Blocks) -> Result<Node, Error<Rule>> {
+    let name = pair
+        .clone()
+        .into_inner()
+        .next()
+        .unwrap()
+        .as_str()
+        .to_string();
+    if !blocks.definitions.contains_key(&name) {
+        return parse_task(pair);
+    }
+    if pair.clone().into_inner().count() > 1 {
+        return Err(custom_error(
+            &pair,
+            format!("block '{name}' can't take durations, accesses, dependencies or `!`"),
+        ));
+    }
+    Ok(blocks.expand(&name))
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_task(pair: Pair<Rule>) -> Result<Node, Error<Rule>> {
     let mut inner = pair.into_inner();
-    let id = inner.next().unwrap().as_str().to_string();
+    let id_pair = inner.next().unwrap();
+    let id = id_pair.as_str().to_string();
 
     let mut deps = vec![];
     let mut terminal = false;
+    let mut attrs = Attrs {
+        span: Some(span_of(&id_pair)),
+        ..Attrs::default()
+    };
 
     for rule in inner {
         match rule.as_rule() {
+            Rule::Duration => {
+                let number = rule.into_inner().next().unwrap();
+                let duration = number.as_str().parse().map_err(|_| {
+                    custom_error(
+                        &number,
+                        format!("duration {} is too large", number.as_str()),
+                    )
+                })?;
+                attrs.duration = Some(duration);
+            }
+            Rule::Access => {
+                for set in rule.into_inner() {
+                    let target = match set.as_rule() {
+                        Rule::Reads => &mut attrs.reads,
+                        _ => &mut attrs.writes,
+                    };
+                    target.extend(set.into_inner().map(|var| var.as_str().to_string()));
+                }
+            }
             Rule::Deps => {
                 for dep in rule.into_inner() {
+                    attrs.dep_spans.push(span_of(&dep));
                     deps.push(Node::Dep(dep.as_str().to_string()));
                 }
             }
@@ -109,5 +669,76 @@ fn parse_task(pair: Pair<Rule>) -> Node {
         }
     }
 
-    Node::Atomic(id, deps, terminal)
+    Ok(Node::Atomic(id, deps, terminal, attrs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{Ir, Unvalidated};
+
+    fn expand(input: &str) -> String {
+        let graph = parse(input).unwrap();
+        crate::graph::Graph::<Node, Ir, Unvalidated>::new(graph.0).to_string()
+    }
+
+    #[test]
+    fn test_every_expansion_is_renamed() {
+        assert_eq!(
+            expand("let a = [x,y#{x}];\n$s0,a,a$"),
+            "$s0,[a.x,a.y#{a.x}],[a#2.x,a#2.y#{a#2.x}]$"
+        );
+    }
+
+    #[test]
+    fn test_dependency_on_block_waits_for_its_last_tasks() {
+        assert_eq!(
+            expand("let f = {[a,b],c};\n$s0,{f,s1},s2#{f}$"),
+            "$s0,{{[f.a,f.b],f.c},s1},s2#{f.b,f.c}$"
+        );
+    }
+
+    #[test]
+    fn test_labels_are_renamed_with_the_block() {
+        assert_eq!(
+            expand("let a = [@l{x,y},z#{@l}];\n$a,a$"),
+            "$[@a.l{a.x,a.y},a.z#{@a.l}],[@a#2.l{a#2.x,a#2.y},a#2.z#{@a#2.l}]$"
+        );
+    }
+
+    #[test]
+    fn test_included_blocks_are_namespaced() {
+        let dir = std::env::temp_dir().join(format!("concurrent-include-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.graph"), "let fan = {a,b};\n$x,fan$\n").unwrap();
+        std::fs::write(dir.join("loop.graph"), "include \"loop.graph\";\n").unwrap();
+
+        let graph = parse_in("include \"lib.graph\" as m;\n$s0,m,m.fan$", &dir).unwrap();
+        let cycle = parse_in("include \"loop.graph\";\n$s0$", &dir).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            crate::graph::Graph::<Node, Ir, Unvalidated>::new(graph.0).to_string(),
+            "$s0,[m.x,{m.fan.a,m.fan.b}],{m.fan#2.a,m.fan#2.b}$"
+        );
+        assert!(cycle.variant.message().contains("include cycle"));
+    }
+
+    #[test]
+    fn test_too_long_duration_is_a_syntax_error() {
+        let error = parse("$s0:99999999999999999999999$").unwrap_err();
+
+        assert!(error.variant.message().contains("too large"));
+    }
+
+    #[test]
+    fn test_parallelize_keeps_only_data_dependencies() {
+        let graph = parse("$s1(w:a),s2(r:a; w:b),s3(w:c),s4(r:b,c),s5$").unwrap();
+        let parallel = Graph::new(graph.0).parallelize();
+
+        assert_eq!(
+            crate::graph::Graph::<Node, Ir, Unvalidated>::new(parallel.0).to_string(),
+            "${[{[s1(w:a),s2(r:a; w:b)],s3(w:c)},s4(r:b,c)],s5}$"
+        );
+    }
 }
@@ -1,24 +1,34 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::fmt::{self, Write};
 use std::marker::PhantomData;
+use std::path::Path;
 
+use crate::error::Span;
 use crate::{ValidationError, ValidationErrorKind};
-use log::warn;
+use log::{debug, warn};
 
 mod cfg;
+mod dag;
 pub mod fk;
 pub mod ir;
 pub mod par;
+pub mod sem;
 
-pub use ir::Node as IrNode;
+pub use ir::{Attrs, Node as IrNode};
 
 pub struct Ir;
 pub struct Par;
 pub struct ForkJoin;
+pub struct Sem;
 
 pub struct Valid;
 pub struct Unvalidated;
 
+/// Byte range of a parsed pair, to point diagnostics at it.
+pub(crate) fn span_of<R: pest::RuleType>(pair: &pest::iterators::Pair<R>) -> Span {
+    pair.as_span().start()..pair.as_span().end()
+}
+
 pub struct Graph<N, K, S = Unvalidated>(pub Vec<N>, PhantomData<K>, PhantomData<S>);
 
 impl<N, K, S> Graph<N, K, S> {
@@ -33,7 +43,13 @@ impl<N, K, S> Graph<N, K, S> {
 
 impl<S> Graph<ir::Node, Ir, S> {
     pub fn parse(input: &str) -> Result<Self, crate::Error> {
-        let g = ir::parse(input).map_err(|e| crate::Error::ParseError(format!("IR: {e}")))?;
+        let g = ir::parse(input)?;
+        Ok(Graph::new(g.0))
+    }
+
+    /// Like [`Graph::parse`], looking up the included files from `dir`.
+    pub fn parse_in(input: &str, dir: &Path) -> Result<Self, crate::Error> {
+        let g = ir::parse_in(input, dir)?;
         Ok(Graph::new(g.0))
     }
 
@@ -51,14 +67,56 @@ impl<S> Graph<ir::Node, Ir, S> {
                 ),
             ]));
         }
+        if ir::has_loops(&self.0) {
+            return Err(crate::Error::InvalidGraph(vec![ValidationError::new(
+                ValidationErrorKind::UnsupportedLoop,
+                "Par cannot represent loops".to_string(),
+            )]));
+        }
+        if ir::has_choices(&self.0) {
+            return Err(crate::Error::InvalidGraph(vec![ValidationError::new(
+                ValidationErrorKind::UnsupportedChoice,
+                "Par cannot represent choices".to_string(),
+            )]));
+        }
         let ir_graph = ir::Graph::new(self.0);
         Ok(Graph::new(ir_graph.to_par().0))
     }
+
+    pub fn parallelize(self) -> Graph<ir::Node, Ir, Unvalidated> {
+        let ir_graph = ir::Graph::new(self.0);
+        Graph::new(ir_graph.parallelize().0)
+    }
+
+    pub fn to_sem(self) -> Result<Graph<sem::Node, Sem, S>, crate::Error> {
+        let ir_graph = ir::Graph::new(self.0);
+        let sem_graph = ir_graph
+            .to_sem()
+            .map_err(|e| crate::Error::InvalidGraph(vec![e]))?;
+        Ok(Graph::new(sem_graph.0))
+    }
+}
+
+impl Graph<ir::Node, Ir, Unvalidated> {
+    /// Inverse of [`Graph::to_petgraph`]: series-parallel structure becomes
+    /// `[]`/`{}` nesting and the remaining edges become `#{}` dependencies.
+    pub fn from_petgraph<E>(graph: &petgraph::Graph<String, E>) -> Result<Self, crate::Error> {
+        if petgraph::algo::is_cyclic_directed(graph) {
+            return Err(crate::Error::InvalidGraph(vec![ValidationError::new(
+                ValidationErrorKind::CircularDependency,
+                "Cannot build a graph from a cyclic precedence graph".to_string(),
+            )]));
+        }
+        Ok(Graph::new(ir::Graph::from_petgraph(graph).0))
+    }
 }
 
 impl<S> Graph<par::Node, Par, S> {
     pub fn parse(input: &str) -> Result<Self, crate::Error> {
-        let g = par::parse(input).map_err(|e| crate::Error::ParseError(format!("Par: {e}")))?;
+        let g = par::parse(input)?;
+        let mut tasks = vec![];
+        collect_par_tasks(&g.0, &mut tasks);
+        crate::validate::check_duplicate_tasks(tasks).map_err(crate::Error::InvalidGraph)?;
         Ok(Graph::new(g.0))
     }
 
@@ -68,20 +126,58 @@ impl<S> Graph<par::Node, Par, S> {
     }
 }
 
+/// A program mapped to a graph along with the warnings found while mapping it.
+pub type Mapped<S> = (Graph<ir::Node, Ir, S>, Vec<ValidationError>);
+
 impl<S> Graph<fk::Stmt, ForkJoin, S> {
     pub fn parse(input: &str) -> Result<Self, crate::Error> {
-        let g = fk::parse(input).map_err(|e| crate::Error::ParseError(format!("ForkJoin: {e}")))?;
-        if let Err(errors) = validate_fk_labels(&g.0) {
+        Self::parse_with_warnings(input).map(|(graph, _)| graph)
+    }
+
+    /// Like [`Graph::parse`], but also returns the warnings about the labels.
+    pub fn parse_with_warnings(input: &str) -> Result<(Self, Vec<ValidationError>), crate::Error> {
+        let g = fk::parse(input)?;
+        let mut errors = vec![];
+        if let Err(labels) = validate_fk_labels(&g.0) {
+            errors.extend(labels);
+        }
+        // `_end: end` parses as a task named `end`.
+        let tasks = g.0.iter().filter_map(|stmt| match &stmt.node {
+            fk::Node::Atomic { id, .. } if id != "end" => Some((id.as_str(), stmt.span.as_ref())),
+            _ => None,
+        });
+        if let Err(duplicates) = crate::validate::check_duplicate_tasks(tasks) {
+            errors.extend(duplicates);
+        }
+        if errors.iter().any(|error| !error.is_warning()) {
             return Err(crate::Error::InvalidGraph(errors));
         }
-        Ok(Graph::new(g.0))
+        Ok((Graph::new(g.0), errors))
     }
 
-    pub fn to_ir(self) -> Graph<ir::Node, Ir, S> {
+    /// Maps the program to a graph, which needs every statement to run and
+    /// every forked thread to reach a join. Returns the warnings found too.
+    pub fn to_ir(self) -> Result<Mapped<S>, crate::Error> {
         let fk_graph = fk::Graph::new(self.0);
-        let g = Graph::new(fk_graph.to_ir().0);
-        println!("{g:?}");
-        g
+        let cfg = cfg::ControlFlowGraph::from_graph(&fk_graph);
+        let warnings = cfg.check_reachability();
+        if warnings.iter().any(|error| !error.is_warning()) {
+            return Err(crate::Error::InvalidGraph(warnings));
+        }
+        let g = Graph::new(cfg.to_ir().0);
+        debug!("{g:?}");
+        Ok((g, warnings))
+    }
+}
+
+fn collect_par_tasks<'a>(nodes: &'a [par::Node], tasks: &mut Vec<(&'a str, Option<&'a Span>)>) {
+    for node in nodes {
+        match node {
+            par::Node::Atomic(id, _, span) => tasks.push((id, span.as_ref())),
+            par::Node::Par(children) | par::Node::Seq(children) => {
+                collect_par_tasks(children, tasks);
+            }
+        }
     }
 }
 
@@ -91,38 +187,28 @@ fn has_dependencies(nodes: &[ir::Node]) -> bool {
 
 fn has_dependencies_node(node: &ir::Node) -> bool {
     match node {
-        ir::Node::Atomic(_, deps, _) => !deps.is_empty(),
-        ir::Node::Par(children) | ir::Node::Seq(children) => {
-            children.iter().any(has_dependencies_node)
-        }
+        ir::Node::Atomic(_, deps, ..) => !deps.is_empty(),
+        ir::Node::Par(children)
+        | ir::Node::Seq(children)
+        | ir::Node::Loop(children)
+        | ir::Node::Choice(children)
+        | ir::Node::Labelled(_, children, _) => children.iter().any(has_dependencies_node),
         ir::Node::Dep(_) => true,
     }
 }
 
 fn validate_fk_labels(stmts: &[fk::Stmt]) -> Result<(), Vec<ValidationError>> {
-    let mut defined: HashMap<String, Vec<(usize, &'static str)>> = HashMap::new();
-    let mut referenced: HashMap<String, Vec<(usize, &'static str)>> = HashMap::new();
+    let mut defined: HashMap<String, Vec<(usize, &fk::Stmt)>> = HashMap::new();
+    let mut referenced: HashMap<String, Vec<(usize, &fk::Stmt)>> = HashMap::new();
 
     for (idx, stmt) in stmts.iter().enumerate() {
         if let Some(label) = &stmt.label {
-            defined
-                .entry(label.clone())
-                .or_default()
-                .push((idx, node_kind(&stmt.node)));
+            defined.entry(label.clone()).or_default().push((idx, stmt));
         }
 
         match &stmt.node {
-            fk::Node::Goto { id } => {
-                referenced
-                    .entry(id.clone())
-                    .or_default()
-                    .push((idx, "goto"));
-            }
-            fk::Node::Fork { id } => {
-                referenced
-                    .entry(id.clone())
-                    .or_default()
-                    .push((idx, "fork"));
+            fk::Node::Goto { id } | fk::Node::Fork { id } | fk::Node::If { id, .. } => {
+                referenced.entry(id.clone()).or_default().push((idx, stmt));
             }
             _ => {}
         }
@@ -133,14 +219,17 @@ fn validate_fk_labels(stmts: &[fk::Stmt]) -> Result<(), Vec<ValidationError>> {
         if !defined.contains_key(label) {
             let refs = sources
                 .iter()
-                .map(|(idx, kind)| format!("{kind}@#{idx}"))
+                .map(|(idx, stmt)| format!("{}@#{idx}", node_kind(&stmt.node)))
                 .collect::<Vec<_>>()
                 .join(", ");
             let message = format!("Label '{label}' referenced by {refs} but not defined",);
-            errors.push(ValidationError::new(
-                ValidationErrorKind::MissingLabel,
-                message.clone(),
-            ));
+            let error = sources.iter().fold(
+                ValidationError::new(ValidationErrorKind::MissingLabel, message.clone()),
+                |error, (_, stmt)| {
+                    error.with_label(stmt.span.clone(), format!("'{label}' is not defined"))
+                },
+            );
+            errors.push(error);
             warn!("{message}");
         }
     }
@@ -150,16 +239,17 @@ fn validate_fk_labels(stmts: &[fk::Stmt]) -> Result<(), Vec<ValidationError>> {
             continue;
         }
         if !referenced.contains_key(label) {
-            let defs = defs
+            let defs_list = defs
                 .iter()
-                .map(|(idx, kind)| format!("{kind}@#{idx}"))
+                .map(|(idx, stmt)| format!("{}@#{idx}", node_kind(&stmt.node)))
                 .collect::<Vec<_>>()
                 .join(", ");
-            let message = format!("Label '{label}' defined at {defs} but never referenced",);
-            errors.push(ValidationError::new(
-                ValidationErrorKind::UnusedLabel,
-                message.clone(),
-            ));
+            let message = format!("Label '{label}' defined at {defs_list} but never referenced",);
+            let error = defs.iter().fold(
+                ValidationError::new(ValidationErrorKind::UnusedLabel, message.clone()),
+                |error, (_, stmt)| error.with_label(stmt.span.clone(), "never referenced"),
+            );
+            errors.push(error);
             warn!("{message}");
         }
     }
@@ -175,13 +265,16 @@ fn node_kind(node: &fk::Node) -> &'static str {
     match node {
         fk::Node::Final => "final",
         fk::Node::Join { .. } => "join",
+        fk::Node::Counter { .. } => "counter",
         fk::Node::Goto { .. } => "goto",
+        fk::Node::Quit => "quit",
         fk::Node::Fork { .. } => "fork",
+        fk::Node::If { .. } => "if",
         fk::Node::Atomic { .. } => "atomic",
     }
 }
 
-fn format_node(node: &ir::Node) -> String {
+pub(crate) fn format_node(node: &ir::Node) -> String {
     match node {
         ir::Node::Par(nodes) => {
             let inner = nodes.iter().map(format_node).collect::<Vec<_>>().join(",");
@@ -191,8 +284,21 @@ fn format_node(node: &ir::Node) -> String {
             let inner = nodes.iter().map(format_node).collect::<Vec<_>>().join(",");
             format!("[{inner}]")
         }
-        ir::Node::Atomic(name, deps, terminal) => {
+        ir::Node::Atomic(name, deps, terminal, attrs) => {
             let mut result = name.clone();
+            if let Some(duration) = attrs.duration {
+                write!(&mut result, ":{duration}").unwrap();
+            }
+            if attrs.has_access() {
+                let mut sets = vec![];
+                if !attrs.reads.is_empty() {
+                    sets.push(format!("r:{}", attrs.reads.join(",")));
+                }
+                if !attrs.writes.is_empty() {
+                    sets.push(format!("w:{}", attrs.writes.join(",")));
+                }
+                write!(&mut result, "({})", sets.join("; ")).unwrap();
+            }
             if !deps.is_empty() {
                 let dep_names = deps
                     .iter()
@@ -210,6 +316,26 @@ fn format_node(node: &ir::Node) -> String {
             result
         }
         ir::Node::Dep(name) => name.clone(),
+        ir::Node::Loop(nodes) => match nodes.as_slice() {
+            [node @ (ir::Node::Atomic(..) | ir::Node::Par(_) | ir::Node::Labelled(..))] => {
+                format!("{}*", format_node(node))
+            }
+            _ => format!("{}*", format_node(&ir::Node::Seq(nodes.clone()))),
+        },
+        ir::Node::Choice(alternatives) => {
+            let inner = alternatives
+                .iter()
+                .map(format_node)
+                .collect::<Vec<_>>()
+                .join("|");
+            format!("({inner})")
+        }
+        ir::Node::Labelled(label, nodes, _) => match nodes.as_slice() {
+            [node @ (ir::Node::Par(_) | ir::Node::Seq(_) | ir::Node::Choice(_))] => {
+                format!("@{label}{}", format_node(node))
+            }
+            _ => format!("@{label}{}", format_node(&ir::Node::Seq(nodes.clone()))),
+        },
     }
 }
 
@@ -233,7 +359,8 @@ impl<S> fmt::Display for Graph<par::Node, Par, S> {
 fn format_par_node(f: &mut fmt::Formatter<'_>, node: &par::Node, indent: usize) -> fmt::Result {
     let pad = "  ".repeat(indent);
     match node {
-        par::Node::Atomic(name) => writeln!(f, "{pad}{name}"),
+        par::Node::Atomic(name, None, _) => writeln!(f, "{pad}{name}"),
+        par::Node::Atomic(name, Some(duration), _) => writeln!(f, "{pad}{name}:{duration}"),
         par::Node::Par(children) => {
             writeln!(f, "{pad}parbegin")?;
             for child in children {
@@ -251,6 +378,42 @@ fn format_par_node(f: &mut fmt::Formatter<'_>, node: &par::Node, indent: usize)
     }
 }
 
+impl<S> fmt::Display for Graph<sem::Node, Sem, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for semaphore in sem::semaphores(&self.0) {
+            writeln!(f, "var {semaphore}: semaphore := 0")?;
+        }
+        writeln!(f, "begin")?;
+        for node in &self.0 {
+            format_sem_node(f, node, 1)?;
+        }
+        write!(f, "end")
+    }
+}
+
+fn format_sem_node(f: &mut fmt::Formatter<'_>, node: &sem::Node, indent: usize) -> fmt::Result {
+    let pad = "  ".repeat(indent);
+    match node {
+        sem::Node::Atomic(name) => writeln!(f, "{pad}{name}"),
+        sem::Node::Wait(semaphore) => writeln!(f, "{pad}P({semaphore})"),
+        sem::Node::Signal(semaphore) => writeln!(f, "{pad}V({semaphore})"),
+        sem::Node::Par(children) => {
+            writeln!(f, "{pad}parbegin")?;
+            for child in children {
+                format_sem_node(f, child, indent + 1)?;
+            }
+            writeln!(f, "{pad}parend")
+        }
+        sem::Node::Seq(children) => {
+            writeln!(f, "{pad}begin")?;
+            for child in children {
+                format_sem_node(f, child, indent + 1)?;
+            }
+            writeln!(f, "{pad}end")
+        }
+    }
+}
+
 impl<S> fmt::Display for Graph<fk::Stmt, ForkJoin, S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "begin")?;
@@ -263,24 +426,36 @@ impl<S> fmt::Display for Graph<fk::Stmt, ForkJoin, S> {
             } else {
                 write!(f, "{indent}")?;
             }
-            match &stmt.node {
-                fk::Node::Final => writeln!(f, "end")?,
-                fk::Node::Atomic { id: name } => writeln!(f, "{name}")?,
-                fk::Node::Fork { id: target } => writeln!(f, "fork {target}")?,
-                fk::Node::Goto { id: target } => {
-                    writeln!(f, "goto {target}")?;
-                    in_branch = false;
-                }
-                fk::Node::Join { id: target } => {
-                    writeln!(f, "join {target}")?;
-                    in_branch = false;
-                }
+            writeln!(f, "{}", stmt.node)?;
+            if matches!(
+                stmt.node,
+                fk::Node::Goto { .. } | fk::Node::Join { .. } | fk::Node::Quit
+            ) {
+                in_branch = false;
             }
         }
         write!(f, "end")
     }
 }
 
+impl fmt::Display for fk::Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            fk::Node::Final => write!(f, "end"),
+            fk::Node::Atomic { id: name, duration } => match duration {
+                Some(duration) => write!(f, "{name}:{duration}"),
+                None => write!(f, "{name}"),
+            },
+            fk::Node::Fork { id: target } => write!(f, "fork {target}"),
+            fk::Node::Goto { id: target } => write!(f, "goto {target}"),
+            fk::Node::If { cond, id: target } => write!(f, "if {cond} goto {target}"),
+            fk::Node::Join { id: target } => write!(f, "join {target}"),
+            fk::Node::Counter { id, count } => write!(f, "{id} := {count}"),
+            fk::Node::Quit => write!(f, "quit"),
+        }
+    }
+}
+
 impl<N: std::fmt::Debug, K, S> std::fmt::Debug for Graph<N, K, S> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_tuple("Graph").field(&self.0).finish()
@@ -1,9 +1,10 @@
 use pest::Parser;
-use pest::error::Error;
-use pest::iterators::Pairs;
+use pest::error::{Error, ErrorVariant};
+use pest::iterators::{Pair, Pairs};
 use pest_derive::Parser;
 
-use super::ir;
+use super::{ir, span_of};
+use crate::error::Span;
 
 #[derive(Debug)]
 pub struct Graph(pub Vec<Node>);
@@ -26,26 +27,41 @@ impl Graph {
 pub enum Node {
     Par(Vec<Node>),
     Seq(Vec<Node>),
-    Atomic(String),
+    /// A task, its duration and where it was written, if known.
+    Atomic(String, Option<u64>, Option<Span>),
 }
 
 fn node_to_ir(node: &Node) -> ir::Node {
     match node {
         Node::Par(children) => ir::Node::Par(children.iter().map(node_to_ir).collect()),
         Node::Seq(children) => ir::Node::Seq(children.iter().map(node_to_ir).collect()),
-        Node::Atomic(name) => ir::Node::Atomic(name.clone(), vec![], false),
+        Node::Atomic(name, duration, span) => ir::Node::Atomic(
+            name.clone(),
+            vec![],
+            false,
+            ir::Attrs {
+                duration: *duration,
+                span: span.clone(),
+                ..ir::Attrs::default()
+            },
+        ),
     }
 }
 
 fn ir_to_node(node: &ir::Node) -> Node {
     match node {
         ir::Node::Par(children) => Node::Par(children.iter().map(ir_to_node).collect()),
-        ir::Node::Seq(children) => Node::Seq(children.iter().map(ir_to_node).collect()),
-        ir::Node::Atomic(name, deps, _) => {
+        // Par has no labels, only what the block runs is kept.
+        ir::Node::Seq(children) | ir::Node::Labelled(_, children, _) => {
+            Node::Seq(children.iter().map(ir_to_node).collect())
+        }
+        ir::Node::Atomic(name, deps, _, attrs) => {
             assert!(deps.is_empty(), "Par cannot represent dependencies");
-            Node::Atomic(name.clone())
+            Node::Atomic(name.clone(), attrs.duration, attrs.span.clone())
         }
         ir::Node::Dep(_) => panic!("Par cannot represent dependencies"),
+        ir::Node::Loop(_) => panic!("Par cannot represent loops"),
+        ir::Node::Choice(_) => panic!("Par cannot represent choices"),
     }
 }
 
@@ -60,59 +76,89 @@ pub fn parse(input: impl AsRef<str>) -> Result<Graph, Error<Rule>> {
         .unwrap();
 
     let mut nodes = vec![];
-    parse_nodes(rule.into_inner(), &mut nodes);
+    parse_nodes(rule.into_inner(), &mut nodes)?;
 
     Ok(Graph::new(nodes))
 }
 
-fn parse_nodes(pairs: Pairs<Rule>, nodes: &mut Vec<Node>) {
+#[allow(clippy::result_large_err)]
+fn parse_nodes(pairs: Pairs<Rule>, nodes: &mut Vec<Node>) -> Result<(), Error<Rule>> {
     for pair in pairs {
         match pair.as_rule() {
-            Rule::Id => nodes.push(Node::Atomic(pair.as_str().to_string())),
-            Rule::Inline => {
-                let id = pair.into_inner().next().unwrap().as_str().to_string();
-                nodes.push(Node::Atomic(id));
-            }
-            Rule::ParBlock => nodes.push(parse_par_block(pair)),
-            Rule::SeqBlock => nodes.push(parse_seq_block(pair)),
+            Rule::Task => nodes.push(parse_task(pair)?),
+            Rule::ParBlock => nodes.push(parse_par_block(pair)?),
+            Rule::SeqBlock => nodes.push(parse_seq_block(pair)?),
             Rule::EOI => break,
             _ => {}
         }
     }
+    Ok(())
 }
 
-fn parse_par_block(pair: pest::iterators::Pair<Rule>) -> Node {
+#[allow(clippy::result_large_err)]
+fn parse_par_block(pair: Pair<Rule>) -> Result<Node, Error<Rule>> {
     let mut children = vec![];
 
     for inner in pair.into_inner() {
         match inner.as_rule() {
-            Rule::Id => children.push(Node::Atomic(inner.as_str().to_string())),
-            Rule::Inline => {
-                let id = inner.into_inner().next().unwrap().as_str().to_string();
-                children.push(Node::Atomic(id));
-            }
-            Rule::SeqBlock => children.push(parse_seq_block(inner)),
+            Rule::Task => children.push(parse_task(inner)?),
+            Rule::SeqBlock => children.push(parse_seq_block(inner)?),
             _ => {}
         }
     }
 
-    Node::Par(children)
+    Ok(Node::Par(children))
 }
 
-fn parse_seq_block(pair: pest::iterators::Pair<Rule>) -> Node {
+#[allow(clippy::result_large_err)]
+fn parse_seq_block(pair: Pair<Rule>) -> Result<Node, Error<Rule>> {
     let mut children = vec![];
 
     for inner in pair.into_inner() {
         match inner.as_rule() {
-            Rule::Id => children.push(Node::Atomic(inner.as_str().to_string())),
-            Rule::Inline => {
-                let id = inner.into_inner().next().unwrap().as_str().to_string();
-                children.push(Node::Atomic(id));
-            }
-            Rule::ParBlock => children.push(parse_par_block(inner)),
+            Rule::Task => children.push(parse_task(inner)?),
+            Rule::ParBlock => children.push(parse_par_block(inner)?),
             _ => {}
         }
     }
 
-    Node::Seq(children)
+    Ok(Node::Seq(children))
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_task(pair: Pair<Rule>) -> Result<Node, Error<Rule>> {
+    let mut inner = pair.into_inner();
+    let id_pair = inner.next().unwrap();
+    let duration = inner
+        .next()
+        .and_then(|duration| duration.into_inner().next())
+        .map(|number| {
+            number.as_str().parse().map_err(|_| {
+                Error::new_from_span(
+                    ErrorVariant::CustomError {
+                        message: format!("duration {} is too large", number.as_str()),
+                    },
+                    number.as_span(),
+                )
+            })
+        })
+        .transpose()?;
+
+    Ok(Node::Atomic(
+        id_pair.as_str().to_string(),
+        duration,
+        Some(span_of(&id_pair)),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_too_long_duration_is_a_syntax_error() {
+        let error = parse("begin\ns0:99999999999999999999999\nend").unwrap_err();
+
+        assert!(error.variant.message().contains("too large"));
+    }
 }
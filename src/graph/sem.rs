@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+
+use petgraph::algo::{has_path_connecting, is_cyclic_directed};
+use petgraph::graph::NodeIndex;
+
+use super::ir;
+use crate::{ValidationError, ValidationErrorKind};
+
+/// parbegin/parend program where the `#{...}` dependencies that the nesting
+/// cannot express are synchronised through semaphores.
+#[derive(Debug)]
+pub struct Graph(pub Vec<Node>);
+
+#[derive(Debug)]
+pub enum Node {
+    Par(Vec<Node>),
+    Seq(Vec<Node>),
+    Atomic(String),
+    /// `P(x)`: waits until the semaphore `x` is signaled.
+    Wait(String),
+    /// `V(x)`: signals the semaphore `x`.
+    Signal(String),
+}
+
+type Precedence = petgraph::Graph<String, ()>;
+
+impl Graph {
+    pub fn new(nodes: Vec<Node>) -> Self {
+        Self(nodes)
+    }
+
+    pub fn from_ir(ir: &ir::Graph) -> Result<Self, ValidationError> {
+        if ir::has_loops(&ir.0) {
+            return Err(ValidationError::new(
+                ValidationErrorKind::UnsupportedLoop,
+                "Semaphore programs cannot represent loops".to_string(),
+            ));
+        }
+        if ir::has_choices(&ir.0) {
+            return Err(ValidationError::new(
+                ValidationErrorKind::UnsupportedChoice,
+                "Semaphore programs cannot represent choices".to_string(),
+            ));
+        }
+
+        let mut graph = Precedence::new();
+        let mut indices = HashMap::new();
+        link_nodes(&ir.0, vec![], &mut graph, &mut indices);
+
+        let mut deps = vec![];
+        collect_deps(&ir.0, &mut deps);
+        let deps: Vec<_> = deps
+            .into_iter()
+            .flat_map(|(from, to)| {
+                ir::dependency_tasks(&ir.0, &from)
+                    .into_iter()
+                    .map(move |from| (from, to.clone()))
+            })
+            .collect();
+
+        for (from, to) in &deps {
+            if let (Some(&a), Some(&b)) = (indices.get(from), indices.get(to)) {
+                graph.add_edge(a, b, ());
+            }
+        }
+
+        if is_cyclic_directed(&graph) {
+            return Err(ValidationError::new(
+                ValidationErrorKind::UnsupportedDependencies,
+                "Dependencies contradict the begin/end nesting".to_string(),
+            ));
+        }
+
+        // Only the dependencies that are not implied by the nesting (or by other
+        // dependencies) need a semaphore.
+        let mut waits: HashMap<String, Vec<String>> = HashMap::new();
+        let mut signals: HashMap<String, Vec<String>> = HashMap::new();
+        let mut semaphores: Vec<String> = vec![];
+        let mut synchronised: Vec<(String, String)> = vec![];
+
+        for (from, to) in deps {
+            let (Some(&a), Some(&b)) = (indices.get(&from), indices.get(&to)) else {
+                continue;
+            };
+            let Some(edge) = graph.find_edge(a, b) else {
+                continue;
+            };
+            graph.remove_edge(edge);
+            if has_path_connecting(&graph, a, b, None) {
+                continue;
+            }
+            graph.add_edge(a, b, ());
+
+            if synchronised.contains(&(from.clone(), to.clone())) {
+                continue;
+            }
+            // `a_b -> c` and `a -> b_c` would share a name, and so a semaphore.
+            let name = format!("{from}_{to}");
+            let taken = |name: &String| semaphores.contains(name) || indices.contains_key(name);
+            let semaphore = std::iter::once(name.clone())
+                .chain((2..).map(|n| format!("{name}_{n}")))
+                .find(|semaphore| !taken(semaphore))
+                .unwrap();
+            synchronised.push((from.clone(), to.clone()));
+            waits.entry(to).or_default().push(semaphore.clone());
+            signals.entry(from).or_default().push(semaphore.clone());
+            semaphores.push(semaphore);
+        }
+
+        let mut nodes = vec![];
+        for node in &ir.0 {
+            nodes.extend(convert_node(node, &waits, &signals));
+        }
+
+        Ok(Graph::new(nodes))
+    }
+}
+
+/// Semaphores used by the program, in order of first appearance.
+pub fn semaphores(nodes: &[Node]) -> Vec<String> {
+    let mut result = vec![];
+    collect_semaphores(nodes, &mut result);
+    result
+}
+
+fn collect_semaphores(nodes: &[Node], result: &mut Vec<String>) {
+    for node in nodes {
+        match node {
+            Node::Wait(semaphore) | Node::Signal(semaphore) => {
+                if !result.contains(semaphore) {
+                    result.push(semaphore.clone());
+                }
+            }
+            Node::Par(children) | Node::Seq(children) => collect_semaphores(children, result),
+            Node::Atomic(_) => {}
+        }
+    }
+}
+
+/// Adds the precedence implied by the begin/end nesting and returns the nodes
+/// that finish the given list. Terminal nodes are still awaited by `parend`.
+fn link_nodes(
+    nodes: &[ir::Node],
+    mut prev: Vec<NodeIndex>,
+    graph: &mut Precedence,
+    indices: &mut HashMap<String, NodeIndex>,
+) -> Vec<NodeIndex> {
+    for node in nodes {
+        prev = link_node(node, prev, graph, indices);
+    }
+    prev
+}
+
+fn link_node(
+    node: &ir::Node,
+    prev: Vec<NodeIndex>,
+    graph: &mut Precedence,
+    indices: &mut HashMap<String, NodeIndex>,
+) -> Vec<NodeIndex> {
+    match node {
+        ir::Node::Atomic(id, ..) => {
+            let idx = graph.add_node(id.clone());
+            indices.insert(id.clone(), idx);
+            for p in prev {
+                graph.add_edge(p, idx, ());
+            }
+            vec![idx]
+        }
+        ir::Node::Seq(children) | ir::Node::Loop(children) | ir::Node::Labelled(_, children, _) => {
+            link_nodes(children, prev, graph, indices)
+        }
+        ir::Node::Par(branches) | ir::Node::Choice(branches) if !branches.is_empty() => branches
+            .iter()
+            .flat_map(|branch| link_node(branch, prev.clone(), graph, indices))
+            .collect(),
+        ir::Node::Par(_) | ir::Node::Choice(_) | ir::Node::Dep(_) => prev,
+    }
+}
+
+fn collect_deps(nodes: &[ir::Node], deps: &mut Vec<(String, String)>) {
+    for node in nodes {
+        match node {
+            ir::Node::Atomic(id, node_deps, ..) => {
+                for dep in node_deps {
+                    if let ir::Node::Dep(dep_id) = dep {
+                        deps.push((dep_id.clone(), id.clone()));
+                    }
+                }
+            }
+            ir::Node::Seq(children)
+            | ir::Node::Par(children)
+            | ir::Node::Loop(children)
+            | ir::Node::Choice(children)
+            | ir::Node::Labelled(_, children, _) => {
+                collect_deps(children, deps);
+            }
+            ir::Node::Dep(_) => {}
+        }
+    }
+}
+
+/// Converts a node into the statements that replace it, wrapping every `P`/`V`
+/// around the task it belongs to.
+fn convert_node(
+    node: &ir::Node,
+    waits: &HashMap<String, Vec<String>>,
+    signals: &HashMap<String, Vec<String>>,
+) -> Vec<Node> {
+    match node {
+        ir::Node::Atomic(id, ..) => {
+            let mut stmts = vec![];
+            if let Some(waits) = waits.get(id) {
+                stmts.extend(waits.iter().cloned().map(Node::Wait));
+            }
+            stmts.push(Node::Atomic(id.clone()));
+            if let Some(signals) = signals.get(id) {
+                stmts.extend(signals.iter().cloned().map(Node::Signal));
+            }
+            stmts
+        }
+        ir::Node::Seq(children) | ir::Node::Loop(children) | ir::Node::Labelled(_, children, _) => {
+            children
+                .iter()
+                .flat_map(|child| convert_node(child, waits, signals))
+                .collect()
+        }
+        ir::Node::Par(branches) | ir::Node::Choice(branches) => {
+            let branches = branches
+                .iter()
+                .map(|branch| {
+                    let mut stmts = convert_node(branch, waits, signals);
+                    if stmts.len() == 1 {
+                        stmts.remove(0)
+                    } else {
+                        Node::Seq(stmts)
+                    }
+                })
+                .collect();
+            vec![Node::Par(branches)]
+        }
+        ir::Node::Dep(_) => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atomic(id: &str, deps: &[&str]) -> ir::Node {
+        ir::Node::Atomic(
+            id.to_string(),
+            deps.iter().map(|d| ir::Node::Dep(d.to_string())).collect(),
+            false,
+            ir::Attrs::default(),
+        )
+    }
+
+    #[test]
+    fn test_implied_dependency_needs_no_semaphore() {
+        let ir = ir::Graph::new(vec![atomic("s0", &[]), atomic("s1", &["s0"])]);
+        let graph = Graph::from_ir(&ir).unwrap();
+
+        assert!(semaphores(&graph.0).is_empty());
+    }
+
+    #[test]
+    fn test_cross_branch_dependency_uses_semaphore() {
+        let ir = ir::Graph::new(vec![ir::Node::Par(vec![
+            ir::Node::Seq(vec![atomic("s1", &[]), atomic("s2", &["s3"])]),
+            atomic("s3", &[]),
+        ])]);
+        let graph = Graph::from_ir(&ir).unwrap();
+
+        assert_eq!(semaphores(&graph.0), vec!["s3_s2".to_string()]);
+    }
+
+    #[test]
+    fn test_semaphore_names_do_not_clash() {
+        let ir = ir::Graph::new(vec![ir::Node::Par(vec![
+            ir::Node::Seq(vec![atomic("a_b", &[]), atomic("a", &[])]),
+            ir::Node::Seq(vec![atomic("c", &["a_b"]), atomic("b_c", &["a"])]),
+        ])]);
+        let graph = Graph::from_ir(&ir).unwrap();
+
+        assert_eq!(
+            semaphores(&graph.0),
+            vec!["a_b_c".to_string(), "a_b_c_2".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_dependency_against_nesting() {
+        let ir = ir::Graph::new(vec![atomic("s0", &["s1"]), atomic("s1", &[])]);
+        let result = Graph::from_ir(&ir);
+
+        assert_eq!(
+            result.unwrap_err().kind,
+            ValidationErrorKind::UnsupportedDependencies
+        );
+    }
+}
@@ -1,28 +1,171 @@
+mod critical_path;
 mod error;
 mod graph;
+mod lint;
+mod orders;
 mod render;
+mod schedule;
+mod simulate;
 mod validate;
 
 use std::path::Path;
 
-pub use error::{Error, ValidationError, ValidationErrorKind};
-pub use graph::{ForkJoin, Graph, Ir, IrNode, Par, Unvalidated, Valid};
+pub use critical_path::{Activity, CriticalPath};
+pub use error::{Error, Severity, Span, ValidationError, ValidationErrorKind};
+pub use graph::{Attrs, ForkJoin, Graph, Ir, IrNode, Par, Sem, Unvalidated, Valid};
+pub use orders::Orders;
+pub use schedule::{Heuristic, Schedule, Slot};
+pub use simulate::{Simulation, Step, Thread, ThreadState};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Format {
     Ir,
     Par,
     ForkJoin,
+    Sem,
+}
+
+/// A program, along with the directory the files it includes are relative to.
+#[derive(Debug, Clone, Copy)]
+pub struct Input<'a> {
+    pub text: &'a str,
+    pub dir: &'a Path,
+}
+
+/// Inline programs include files relative to the current directory.
+impl<'a> From<&'a str> for Input<'a> {
+    fn from(text: &'a str) -> Self {
+        Self {
+            text,
+            dir: Path::new(""),
+        }
+    }
+}
+
+/// Tweaks applied when drawing a graph.
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// Highlight the tasks and edges on the critical path.
+    pub critical_path: bool,
+    /// Size of raster images relative to the SVG, where `1.0` is 96 DPI.
+    pub scale: f32,
+    /// Draw text with plain ASCII instead of box-drawing characters.
+    pub ascii: bool,
+    /// Draw text as the tasks of every layer, one line each.
+    pub compact: bool,
+}
+
+/// Pixels per inch of an SVG drawn at scale `1.0`.
+pub const SVG_DPI: f32 = 96.0;
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            critical_path: false,
+            scale: 1.0,
+            ascii: false,
+            compact: false,
+        }
+    }
+}
+
+/// How strict to be with warnings.
+#[derive(Debug, Clone, Default)]
+pub struct WarningPolicy {
+    /// Turn every reported warning into an error.
+    pub deny_warnings: bool,
+    /// Kinds of warnings that aren't reported at all.
+    pub allow: Vec<ValidationErrorKind>,
+}
+
+impl WarningPolicy {
+    /// Drops the allowed warnings and returns the rest, or fails with them as
+    /// errors when warnings are denied.
+    pub fn apply(&self, warnings: Vec<ValidationError>) -> Result<Vec<ValidationError>, Error> {
+        let mut warnings: Vec<_> = warnings
+            .into_iter()
+            .filter(|warning| !self.allow.contains(&warning.kind))
+            .collect();
+
+        if self.deny_warnings && !warnings.is_empty() {
+            for warning in &mut warnings {
+                warning.severity = Severity::Error;
+            }
+            return Err(Error::InvalidGraph(warnings));
+        }
+
+        Ok(warnings)
+    }
 }
 
 pub struct ValidatedGraph {
+    nodes: Vec<IrNode>,
     petgraph: petgraph::Graph<String, &'static str>,
+    critical_path: CriticalPath,
+    warnings: Vec<ValidationError>,
 }
 
 impl ValidatedGraph {
     #[must_use]
     pub fn render_to_svg(&self) -> String {
-        render::render_to_svg(&self.petgraph)
+        self.render_to_svg_with(&RenderOptions::default())
+    }
+
+    #[must_use]
+    pub fn render_to_svg_with(&self, options: &RenderOptions) -> String {
+        let critical = options.critical_path.then_some(&self.critical_path);
+        render::render_to_svg(&self.nodes, &self.petgraph, critical)
+    }
+
+    /// The graph as Graphviz DOT, with a cluster for every block.
+    #[must_use]
+    pub fn render_to_dot(&self) -> String {
+        self.render_to_dot_with(&RenderOptions::default())
+    }
+
+    #[must_use]
+    pub fn render_to_dot_with(&self, options: &RenderOptions) -> String {
+        let critical = options.critical_path.then_some(&self.critical_path);
+        render::render_dot(&self.nodes, &self.petgraph, critical)
+    }
+
+    /// The graph as a Mermaid flowchart, to embed in Markdown.
+    #[must_use]
+    pub fn render_to_mermaid(&self) -> String {
+        self.render_to_mermaid_with(&RenderOptions::default())
+    }
+
+    #[must_use]
+    pub fn render_to_mermaid_with(&self, options: &RenderOptions) -> String {
+        let critical = options.critical_path.then_some(&self.critical_path);
+        render::render_mermaid(&self.nodes, &self.petgraph, critical)
+    }
+
+    /// The graph drawn with characters, to look at it in a terminal.
+    #[must_use]
+    pub fn render_to_text(&self) -> String {
+        self.render_to_text_with(&RenderOptions::default())
+    }
+
+    #[must_use]
+    pub fn render_to_text_with(&self, options: &RenderOptions) -> String {
+        let critical = options.critical_path.then_some(&self.critical_path);
+        let style = render::TextStyle {
+            ascii: options.ascii,
+            compact: options.compact,
+        };
+        render::render_text(&self.petgraph, critical, style)
+    }
+
+    #[must_use]
+    pub fn critical_path(&self) -> &CriticalPath {
+        &self.critical_path
+    }
+
+    /// Problems found on the way that don't make the graph invalid.
+    #[must_use]
+    pub fn warnings(&self) -> &[ValidationError] {
+        &self.warnings
     }
 
     #[must_use]
@@ -36,20 +179,55 @@ impl ValidatedGraph {
     }
 }
 
-pub fn parse_and_validate(input: &str, format: Format) -> Result<ValidatedGraph, Error> {
-    let graph = parse(input, format)?;
+pub fn parse_and_validate<'a>(
+    input: impl Into<Input<'a>>,
+    format: Format,
+) -> Result<ValidatedGraph, Error> {
+    let (graph, mut warnings) = parse_with_warnings(input, format)?;
 
-    let validated = graph.validate()?;
+    let (validated, more) = graph.validate_with_warnings().map_err(|mut errors| {
+        errors.append(&mut warnings);
+        Error::InvalidGraph(errors)
+    })?;
+    warnings.extend(more);
     Ok(ValidatedGraph {
-        petgraph: validated.to_petgraph(),
+        petgraph: validated.to_petgraph_with_loops(),
+        critical_path: validated.critical_path()?,
+        nodes: validated.0,
+        warnings,
     })
 }
 
-pub fn parse(input: &str, format: Format) -> Result<Graph<IrNode, Ir, Unvalidated>, Error> {
+pub fn parse<'a>(
+    input: impl Into<Input<'a>>,
+    format: Format,
+) -> Result<Graph<IrNode, Ir, Unvalidated>, Error> {
+    parse_with_warnings(input, format).map(|(ir, _)| ir)
+}
+
+/// A parsed graph along with the warnings found while parsing it.
+pub type Parsed = (Graph<IrNode, Ir, Unvalidated>, Vec<ValidationError>);
+
+/// Like [`parse`], but also returns the warnings found while parsing.
+pub fn parse_with_warnings<'a>(
+    input: impl Into<Input<'a>>,
+    format: Format,
+) -> Result<Parsed, Error> {
+    let Input { text: input, dir } = input.into();
     let ir = match format {
-        Format::Ir => Graph::<IrNode, Ir>::parse(input)?,
-        Format::Par => Graph::<graph::par::Node, Par>::parse(input)?.to_ir(),
-        Format::ForkJoin => Graph::<graph::fk::Stmt, ForkJoin>::parse(input)?.to_ir(),
+        Format::Ir => (Graph::<IrNode, Ir>::parse_in(input, dir)?, vec![]),
+        Format::Par => (
+            Graph::<graph::par::Node, Par>::parse(input)?.to_ir(),
+            vec![],
+        ),
+        Format::ForkJoin => {
+            let (graph, mut warnings) =
+                Graph::<graph::fk::Stmt, ForkJoin>::parse_with_warnings(input)?;
+            let (ir, reachability) = graph.to_ir()?;
+            warnings.extend(reachability);
+            (ir, warnings)
+        }
+        Format::Sem => return Err(Error::InvalidType("sem (output only)".to_string())),
     };
 
     Ok(ir)
@@ -60,21 +238,89 @@ pub fn render_to_pdf(svg: &str, path: &Path) -> Result<(), Error> {
         .map_err(|e| Error::RenderError(format!("Failed to render PDF: {e}")))
 }
 
-pub fn process_graph_to_pdf(input: &str, output_path: &Path, ext: &str) -> Result<(), Error> {
+pub fn render_to_png(svg: &str, path: &Path, scale: f32) -> Result<(), Error> {
+    render::render_svg_to_png(svg, path, scale)
+        .map_err(|e| Error::RenderError(format!("Failed to render PNG: {e}")))
+}
+
+pub fn render_gantt_to_pdf(schedule: &Schedule, path: &Path) -> Result<(), Error> {
+    render_to_pdf(&render::render_gantt(schedule), path)
+}
+
+pub fn process_graph_to_pdf<'a>(
+    input: impl Into<Input<'a>>,
+    output_path: &Path,
+    ext: &str,
+    options: &RenderOptions,
+) -> Result<(), Error> {
     let format = ext.try_into()?;
     let graph = parse_and_validate(input, format)?;
-    let svg = graph.render_to_svg();
+    let svg = graph.render_to_svg_with(options);
     render_to_pdf(&svg, output_path)
 }
 
-pub fn process_graph_to_ir(input: &str, output_path: &Path, ext: &str) -> Result<(), Error> {
+pub fn process_graph_to_svg<'a>(
+    input: impl Into<Input<'a>>,
+    output_path: &Path,
+    ext: &str,
+    options: &RenderOptions,
+) -> Result<(), Error> {
     let format = ext.try_into()?;
+    let graph = parse_and_validate(input, format)?;
+    let svg = graph.render_to_svg_with(options);
+    std::fs::write(output_path.with_extension("svg"), svg)
+        .map_err(|e| Error::RenderError(format!("Failed to write SVG: {e}")))
+}
 
-    let ir = match format {
-        Format::Ir => Graph::<IrNode, Ir>::parse(input)?,
-        Format::Par => Graph::<graph::par::Node, Par>::parse(input)?.to_ir(),
-        Format::ForkJoin => Graph::<graph::fk::Stmt, ForkJoin>::parse(input)?.to_ir(),
-    };
+pub fn process_graph_to_png<'a>(
+    input: impl Into<Input<'a>>,
+    output_path: &Path,
+    ext: &str,
+    options: &RenderOptions,
+) -> Result<(), Error> {
+    let format = ext.try_into()?;
+    let graph = parse_and_validate(input, format)?;
+    let svg = graph.render_to_svg_with(options);
+    render_to_png(&svg, output_path, options.scale)
+}
+
+pub fn process_graph_to_dot<'a>(
+    input: impl Into<Input<'a>>,
+    ext: &str,
+    options: &RenderOptions,
+) -> Result<String, Error> {
+    let format = ext.try_into()?;
+    let graph = parse_and_validate(input, format)?;
+    Ok(graph.render_to_dot_with(options))
+}
+
+pub fn process_graph_to_mermaid<'a>(
+    input: impl Into<Input<'a>>,
+    ext: &str,
+    options: &RenderOptions,
+) -> Result<String, Error> {
+    let format = ext.try_into()?;
+    let graph = parse_and_validate(input, format)?;
+    Ok(graph.render_to_mermaid_with(options))
+}
+
+pub fn process_graph_to_text<'a>(
+    input: impl Into<Input<'a>>,
+    ext: &str,
+    options: &RenderOptions,
+) -> Result<String, Error> {
+    let format = ext.try_into()?;
+    let graph = parse_and_validate(input, format)?;
+    Ok(graph.render_to_text_with(options))
+}
+
+pub fn process_graph_to_ir<'a>(
+    input: impl Into<Input<'a>>,
+    output_path: &Path,
+    ext: &str,
+) -> Result<(), Error> {
+    let format = ext.try_into()?;
+    let ir = parse(input, format)?;
 
     std::fs::write(output_path, format!("{ir}"))
         .map_err(|e| Error::RenderError(format!("Failed to write IR: {e}")))?;
@@ -82,13 +328,11 @@ pub fn process_graph_to_ir(input: &str, output_path: &Path, ext: &str) -> Result
     Ok(())
 }
 
-fn format_from_ext(ext: &str) -> Result<Format, Error> {
-    match ext {
-        "graph" => Ok(Format::Ir),
-        "par" => Ok(Format::Par),
-        "fk" => Ok(Format::ForkJoin),
-        _ => Err(Error::InvalidType(ext.to_string())),
-    }
+/// Runs the program as written: undefined labels are left for the
+/// interpreter to report as they are reached.
+pub fn simulate(input: &str) -> Result<Simulation, Error> {
+    let graph = graph::fk::parse(input)?;
+    Ok(Graph::<graph::fk::Stmt, ForkJoin>::new(graph.0).simulate())
 }
 
 impl TryFrom<&str> for Format {
@@ -99,14 +343,31 @@ impl TryFrom<&str> for Format {
             "graph" => Ok(Format::Ir),
             "par" => Ok(Format::Par),
             "fk" => Ok(Format::ForkJoin),
+            "sem" => Ok(Format::Sem),
             _ => Err(Error::InvalidType(ext.to_string())),
         }
     }
 }
 
-pub fn convert_graph(input: &str, output: &Path, ex: &str) -> Result<(), Error> {
+/// Tweaks applied before converting a graph to another format.
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// Drop the `#{}` dependencies already implied by the graph.
+    pub remove_redundant_deps: bool,
+}
+
+pub fn convert_graph<'a>(
+    input: impl Into<Input<'a>>,
+    output: &Path,
+    ex: &str,
+    options: &ConvertOptions,
+) -> Result<(), Error> {
     let format = ex.try_into()?;
-    let graph = parse(input, format)?;
+    let mut graph = parse(input, format)?;
+    if options.remove_redundant_deps {
+        let fixed = graph.validate()?.remove_redundant_dependencies();
+        graph = Graph::new(fixed.0);
+    }
 
     let output_ext = output
         .extension()
@@ -120,6 +381,7 @@ pub fn convert_graph(input: &str, output: &Path, ex: &str) -> Result<(), Error>
         Format::Ir => graph.to_string(),
         Format::Par => graph.to_par()?.to_string(),
         Format::ForkJoin => graph.to_fk().to_string(),
+        Format::Sem => graph.validate()?.to_sem()?.to_string(),
     };
 
     std::fs::write(output, graph)
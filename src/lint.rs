@@ -0,0 +1,207 @@
+use std::collections::HashSet;
+
+use petgraph::algo::has_path_connecting;
+use petgraph::visit::{EdgeFiltered, EdgeRef};
+
+use crate::error::{ValidationError, ValidationErrorKind};
+use crate::graph::{Graph, Ir, Valid, format_node, ir};
+
+/// A `#{}` dependency that doesn't add any precedence to the graph.
+struct Redundant {
+    task: String,
+    dep: String,
+    /// Whether the dependency already precedes the task directly, through the
+    /// order of a sequence, rather than through other tasks.
+    direct: bool,
+}
+
+impl Graph<ir::Node, Ir, Valid> {
+    /// Warns about the `#{}` dependencies implied by the sequence order or by
+    /// other dependencies, suggesting the task without them.
+    pub fn redundant_dependencies(&self) -> Vec<ValidationError> {
+        let mut redundant = self.find_redundant();
+        let mut warnings = vec![];
+        lint_nodes(&self.0, &mut redundant, &mut warnings);
+        warnings
+    }
+
+    /// Drops every dependency reported by [`Graph::redundant_dependencies`].
+    pub fn remove_redundant_dependencies(mut self) -> Self {
+        let mut redundant = self.find_redundant();
+        fix_nodes(&mut self.0, &mut redundant);
+        self
+    }
+
+    /// Dependency edges whose target stays reachable without them. They are
+    /// dropped one at a time, so two dependencies that imply each other (like
+    /// a repeated one) don't both go away.
+    fn find_redundant(&self) -> Vec<Redundant> {
+        let graph = self.to_petgraph();
+        let mut removed = HashSet::new();
+        let mut redundant = vec![];
+
+        for edge in graph
+            .edge_references()
+            .filter(|edge| *edge.weight() == "dep")
+        {
+            let id = edge.id();
+            let filtered =
+                EdgeFiltered::from_fn(&graph, |e| e.id() != id && !removed.contains(&e.id()));
+            if !has_path_connecting(&filtered, edge.source(), edge.target(), None) {
+                continue;
+            }
+
+            let direct = graph
+                .edges_connecting(edge.source(), edge.target())
+                .any(|e| *e.weight() != "dep");
+            removed.insert(id);
+            redundant.push(Redundant {
+                task: graph[edge.target()].clone(),
+                dep: graph[edge.source()].clone(),
+                direct,
+            });
+        }
+
+        redundant
+    }
+}
+
+/// Takes the entry for `task` depending on `dep` out of `redundant`, if any.
+fn take(redundant: &mut Vec<Redundant>, task: &str, dep: &str) -> Option<Redundant> {
+    let position = redundant
+        .iter()
+        .position(|r| r.task == task && r.dep == dep)?;
+    Some(redundant.remove(position))
+}
+
+fn lint_nodes(
+    nodes: &[ir::Node],
+    redundant: &mut Vec<Redundant>,
+    warnings: &mut Vec<ValidationError>,
+) {
+    for node in nodes {
+        match node {
+            ir::Node::Atomic(id, deps, terminal, attrs) => {
+                let mut found = vec![];
+                for (i, dep) in deps.iter().enumerate() {
+                    if let ir::Node::Dep(dep_id) = dep
+                        && let Some(entry) = take(redundant, id, dep_id)
+                    {
+                        found.push((i, entry));
+                    }
+                }
+                if found.is_empty() {
+                    continue;
+                }
+
+                let kept: Vec<_> = deps
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| !found.iter().any(|(j, _)| i == j))
+                    .map(|(_, dep)| dep.clone())
+                    .collect();
+                let rewrite = format_node(&ir::Node::Atomic(
+                    id.clone(),
+                    kept,
+                    *terminal,
+                    attrs.clone(),
+                ));
+
+                for (i, entry) in found {
+                    let reason = if entry.direct {
+                        format!("'{}' already runs right before '{id}'", entry.dep)
+                    } else {
+                        format!(
+                            "'{}' already precedes '{id}' through other tasks",
+                            entry.dep
+                        )
+                    };
+                    warnings.push(
+                        ValidationError::new(
+                            ValidationErrorKind::RedundantDependency,
+                            format!(
+                                "Dependency of '{id}' on '{}' is redundant: {reason}",
+                                entry.dep
+                            ),
+                        )
+                        .with_label(attrs.dep_spans.get(i).cloned(), "redundant dependency")
+                        .with_help(format!("write `{rewrite}`")),
+                    );
+                }
+            }
+            ir::Node::Seq(children)
+            | ir::Node::Par(children)
+            | ir::Node::Loop(children)
+            | ir::Node::Choice(children)
+            | ir::Node::Labelled(_, children, _) => {
+                lint_nodes(children, redundant, warnings);
+            }
+            ir::Node::Dep(_) => {}
+        }
+    }
+}
+
+fn fix_nodes(nodes: &mut [ir::Node], redundant: &mut Vec<Redundant>) {
+    for node in nodes {
+        match node {
+            ir::Node::Atomic(id, deps, _, attrs) => {
+                let mut i = 0;
+                while i < deps.len() {
+                    let is_redundant = match &deps[i] {
+                        ir::Node::Dep(dep_id) => take(redundant, id, dep_id).is_some(),
+                        _ => false,
+                    };
+                    if is_redundant {
+                        deps.remove(i);
+                        if i < attrs.dep_spans.len() {
+                            attrs.dep_spans.remove(i);
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+            ir::Node::Seq(children)
+            | ir::Node::Par(children)
+            | ir::Node::Loop(children)
+            | ir::Node::Choice(children)
+            | ir::Node::Labelled(_, children, _) => {
+                fix_nodes(children, redundant);
+            }
+            ir::Node::Dep(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(input: &str) -> Graph<ir::Node, Ir, Valid> {
+        Graph::new(ir::parse(input).unwrap().0)
+    }
+
+    #[test]
+    fn test_sequence_order_dependency() {
+        let warnings = graph("$s0,[s1,s2#{s1}]$").redundant_dependencies();
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].help.as_deref(), Some("write `s2`"));
+    }
+
+    #[test]
+    fn test_transitive_dependency_is_removed() {
+        let fixed = graph("$s0,{[s1,s2],s3#{s1,s2}}$").remove_redundant_dependencies();
+
+        assert_eq!(fixed.to_string(), "$s0,{[s1,s2],s3#{s2}}$");
+    }
+
+    #[test]
+    fn test_needed_dependency_is_kept() {
+        assert!(
+            graph("$s0,{[s1,s2#{s4}],[s3,s4]}$")
+                .redundant_dependencies()
+                .is_empty()
+        );
+    }
+}
@@ -3,103 +3,415 @@ use cli::cli;
 
 use std::path::PathBuf;
 
-use concurrent::{Error, convert_graph, process_graph_to_ir, process_graph_to_pdf};
+use miette::{Diagnostic, NamedSource};
+
+use concurrent::{
+    ConvertOptions, Error, Format, Heuristic, Input, RenderOptions, SVG_DPI, ValidationError,
+    ValidationErrorKind, WarningPolicy, convert_graph, parse, parse_and_validate,
+    process_graph_to_dot, process_graph_to_ir, process_graph_to_mermaid, process_graph_to_pdf,
+    process_graph_to_png, process_graph_to_svg, process_graph_to_text, render_gantt_to_pdf,
+    simulate,
+};
 
 type AppResult<T> = Result<T, Error>;
 
 const DEFAULT_OUTPUT: &str = "render/output.pdf";
 
-fn main() -> AppResult<()> {
+/// The program being processed, kept to point diagnostics at it.
+struct Source {
+    name: String,
+    text: String,
+    ext: String,
+    /// Directory the files it includes are relative to.
+    dir: PathBuf,
+}
+
+fn main() -> miette::Result<()> {
     let cmd = cli();
 
-    match cmd.subcommand() {
+    let (name, args) = match cmd.subcommand() {
         Some(("render", render_cmd)) => match render_cmd.subcommand() {
-            Some(("pdf", args)) => render_pdf(args),
-            Some(("ir", args)) => render_ir(args),
-            _ => Err(Error::InvalidParams),
+            Some(format) => format,
+            None => (render_format(render_cmd)?, render_cmd),
         },
-        Some(("convert", cmd)) => convert(cmd),
+        Some(command) => command,
+        None => return Err(Error::InvalidParams.into()),
+    };
+    let inline_ext = if name == "simulate" { "fk" } else { "graph" };
+    let source = read_input(args, inline_ext)?;
+
+    let policy = warning_policy(args)?;
+    let warnings = policy
+        .apply(warnings(&source))
+        .map_err(|error| source.report(error))?;
+    for warning in warnings {
+        eprintln!("{:?}", source.report(warning));
+    }
+
+    let result = match name {
+        "pdf" => render_pdf(args, &source),
+        "svg" => render_svg(args, &source),
+        "png" => render_png(args, &source),
+        "dot" => render_dot(args, &source),
+        "mermaid" => render_mermaid(args, &source),
+        "text" => render_text(args, &source),
+        "ir" => render_ir(args, &source),
+        "convert" => convert(args, &source),
+        "simulate" => run_simulation(&source),
+        "orders" => orders(args, &source),
+        "parallelize" => parallelize(args, &source),
+        "critical-path" => critical_path(&source),
+        "schedule" => schedule(args, &source),
+        "lint" => lint(args, &source),
         _ => Err(Error::InvalidParams),
-    }?;
+    };
 
-    Ok(())
+    result.map_err(|error| source.report(error))
+}
+
+impl Source {
+    fn input(&self) -> Input<'_> {
+        Input {
+            text: &self.text,
+            dir: &self.dir,
+        }
+    }
+
+    /// Attaches the source to a diagnostic so its labels can be shown.
+    fn report(&self, diagnostic: impl Diagnostic + Send + Sync + 'static) -> miette::Report {
+        miette::Report::new(diagnostic)
+            .with_source_code(NamedSource::new(&self.name, self.text.clone()))
+    }
+}
+
+fn warning_policy(args: &clap::ArgMatches) -> AppResult<WarningPolicy> {
+    let allow = args
+        .get_many::<String>("allow")
+        .unwrap_or_default()
+        .map(|kind| ValidationErrorKind::try_from(kind.as_str()))
+        .collect::<AppResult<_>>()?;
+
+    Ok(WarningPolicy {
+        deny_warnings: args.get_flag("deny-warnings"),
+        allow,
+    })
 }
 
-fn render_pdf(args: &clap::ArgMatches) -> AppResult<()> {
-    let (input, ext) = if let Some(inline) = args.get_one::<String>("input") {
-        (inline.clone(), "graph")
+/// Warnings about the source. Invalid programs have none here: their errors,
+/// along with the warnings, are reported by the command itself.
+fn warnings(source: &Source) -> Vec<ValidationError> {
+    Format::try_from(source.ext.as_str())
+        .and_then(|format| parse_and_validate(source.input(), format))
+        .map(|graph| graph.warnings().to_vec())
+        .unwrap_or_default()
+}
+
+/// Reads the source from `--input` or `--file`.
+///
+/// Inline input is assumed to be written in `inline_ext`.
+fn read_input(args: &clap::ArgMatches, inline_ext: &str) -> AppResult<Source> {
+    if let Some(inline) = args.get_one::<String>("input") {
+        Ok(Source {
+            name: "<input>".to_string(),
+            text: inline.clone(),
+            ext: inline_ext.to_string(),
+            dir: PathBuf::new(),
+        })
     } else if let Some(file_path) = args.get_one::<PathBuf>("file") {
         let ext = file_path
             .extension()
             .expect("File must have an extension")
             .to_str()
             .unwrap();
-        (
-            std::fs::read_to_string(file_path)
+        Ok(Source {
+            name: file_path.display().to_string(),
+            text: std::fs::read_to_string(file_path)
                 .map_err(|e| Error::ParseError(format!("Failed to read file: {e}")))?,
-            ext,
-        )
+            ext: ext.to_string(),
+            dir: file_path.parent().map(PathBuf::from).unwrap_or_default(),
+        })
     } else {
-        return Err(Error::InvalidParams);
-    };
+        Err(Error::InvalidParams)
+    }
+}
+
+/// Picks the format of a bare `render` from the extension of its output.
+fn render_format(args: &clap::ArgMatches) -> AppResult<&'static str> {
+    let output = args
+        .get_one::<PathBuf>("output")
+        .ok_or(Error::InvalidParams)?;
+    let ext = output
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default();
+
+    match ext {
+        "pdf" => Ok("pdf"),
+        "svg" => Ok("svg"),
+        "png" => Ok("png"),
+        "dot" | "gv" => Ok("dot"),
+        "mmd" => Ok("mermaid"),
+        "txt" => Ok("text"),
+        "graph" => Ok("ir"),
+        _ => Err(Error::InvalidType(ext.to_string())),
+    }
+}
+
+fn render_options(args: &clap::ArgMatches) -> RenderOptions {
+    // Only the raster formats take a scale, and only text is drawn in ASCII.
+    let scale = args.try_get_one::<f32>("scale").ok().flatten().copied();
+    let dpi = args.try_get_one::<f32>("dpi").ok().flatten().copied();
+    let flag = |name| args.try_get_one::<bool>(name).ok().flatten() == Some(&true);
+
+    RenderOptions {
+        critical_path: args.get_flag("critical-path"),
+        scale: scale.or(dpi.map(|dpi| dpi / SVG_DPI)).unwrap_or(1.0),
+        ascii: flag("ascii"),
+        compact: flag("compact"),
+    }
+}
 
+fn render_pdf(args: &clap::ArgMatches, source: &Source) -> AppResult<()> {
     let output_path = args
         .get_one::<PathBuf>("output")
         .cloned()
         .unwrap_or(PathBuf::from(DEFAULT_OUTPUT));
 
-    process_graph_to_pdf(&input, &output_path, ext)
+    let options = render_options(args);
+
+    process_graph_to_pdf(source.input(), &output_path, &source.ext, &options)
 }
 
-fn render_ir(args: &clap::ArgMatches) -> AppResult<()> {
-    let (input, ext) = if let Some(inline) = args.get_one::<String>("input") {
-        (inline.clone(), "graph")
-    } else if let Some(file_path) = args.get_one::<PathBuf>("file") {
-        let ext = file_path
-            .extension()
-            .expect("File must have an extension")
-            .to_str()
-            .unwrap();
-        (
-            std::fs::read_to_string(file_path)
-                .map_err(|e| Error::ParseError(format!("Failed to read file: {e}")))?,
-            ext,
-        )
-    } else {
-        return Err(Error::InvalidParams);
-    };
+fn render_dot(args: &clap::ArgMatches, source: &Source) -> AppResult<()> {
+    let options = render_options(args);
+    let dot = process_graph_to_dot(source.input(), &source.ext, &options)?;
+
+    match args.get_one::<PathBuf>("output") {
+        Some(output) => std::fs::write(output, dot)
+            .map_err(|e| Error::RenderError(format!("Failed to write DOT: {e}")))?,
+        None => print!("{dot}"),
+    }
+
+    Ok(())
+}
+
+fn render_mermaid(args: &clap::ArgMatches, source: &Source) -> AppResult<()> {
+    let options = render_options(args);
+    let mermaid = process_graph_to_mermaid(source.input(), &source.ext, &options)?;
+
+    match args.get_one::<PathBuf>("output") {
+        Some(output) => std::fs::write(output, mermaid)
+            .map_err(|e| Error::RenderError(format!("Failed to write Mermaid: {e}")))?,
+        None => print!("{mermaid}"),
+    }
+
+    Ok(())
+}
 
+fn render_svg(args: &clap::ArgMatches, source: &Source) -> AppResult<()> {
     let output_path = args
         .get_one::<PathBuf>("output")
         .cloned()
         .unwrap_or(PathBuf::from(DEFAULT_OUTPUT));
 
-    process_graph_to_ir(&input, &output_path, ext)
+    process_graph_to_svg(
+        source.input(),
+        &output_path,
+        &source.ext,
+        &render_options(args),
+    )
 }
 
-fn convert(args: &clap::ArgMatches) -> AppResult<()> {
-    let (input, ext) = if let Some(inline) = args.get_one::<String>("input") {
-        (inline.clone(), "graph")
-    } else if let Some(file_path) = args.get_one::<PathBuf>("file") {
-        let ext = file_path
-            .extension()
-            .expect("File must have an extension")
-            .to_str()
-            .unwrap();
-        (
-            std::fs::read_to_string(file_path)
-                .map_err(|e| Error::ParseError(format!("Failed to read file: {e}")))?,
-            ext,
-        )
-    } else {
-        return Err(Error::InvalidParams);
-    };
+fn render_png(args: &clap::ArgMatches, source: &Source) -> AppResult<()> {
+    let output_path = args
+        .get_one::<PathBuf>("output")
+        .cloned()
+        .unwrap_or(PathBuf::from(DEFAULT_OUTPUT));
+
+    process_graph_to_png(
+        source.input(),
+        &output_path,
+        &source.ext,
+        &render_options(args),
+    )
+}
+
+fn render_text(args: &clap::ArgMatches, source: &Source) -> AppResult<()> {
+    let text = process_graph_to_text(source.input(), &source.ext, &render_options(args))?;
+
+    match args.get_one::<PathBuf>("output") {
+        Some(output) => std::fs::write(output, text)
+            .map_err(|e| Error::RenderError(format!("Failed to write text: {e}")))?,
+        None => print!("{text}"),
+    }
+
+    Ok(())
+}
+
+fn render_ir(args: &clap::ArgMatches, source: &Source) -> AppResult<()> {
+    let output_path = args
+        .get_one::<PathBuf>("output")
+        .cloned()
+        .unwrap_or(PathBuf::from(DEFAULT_OUTPUT));
+
+    process_graph_to_ir(source.input(), &output_path, &source.ext)
+}
 
+fn convert(args: &clap::ArgMatches, source: &Source) -> AppResult<()> {
     let output_ext = args
         .get_one::<PathBuf>("output")
         .expect("Output extension is required");
 
-    convert_graph(&input, output_ext, ext)?;
+    let options = ConvertOptions {
+        remove_redundant_deps: args.get_flag("fix"),
+    };
+
+    convert_graph(source.input(), output_ext, &source.ext, &options)?;
+
+    Ok(())
+}
+
+fn run_simulation(source: &Source) -> AppResult<()> {
+    if source.ext != "fk" {
+        return Err(Error::InvalidType(source.ext.clone()));
+    }
+
+    let simulation = simulate(&source.text)?;
+    println!("{simulation}");
+
+    if simulation.is_ok() {
+        return Ok(());
+    }
+    let problems: Vec<_> = [
+        (simulation.deadlocked().len(), "deadlocked"),
+        (simulation.lost().len(), "lost"),
+        (simulation.running().len(), "still running"),
+    ]
+    .into_iter()
+    .filter(|&(threads, _)| threads > 0)
+    .map(|(threads, state)| format!("{threads} thread(s) {state}"))
+    .collect();
+    Err(Error::SimulationFailed(problems.join(", ")))
+}
+
+fn orders(args: &clap::ArgMatches, source: &Source) -> AppResult<()> {
+    let format: Format = source.ext.as_str().try_into()?;
+    let graph = parse(source.input(), format)?.validate()?;
+
+    if args.get_flag("count") {
+        println!("{}", graph.count_orders()?);
+    } else if let Some(seed) = args.get_one::<u64>("random") {
+        println!("{}", graph.random_order(*seed)?.join(" "));
+    } else {
+        let limit = args
+            .get_one::<usize>("limit")
+            .copied()
+            .unwrap_or(usize::MAX);
+        for order in graph.orders().take(limit) {
+            println!("{}", order.join(" "));
+        }
+    }
+
+    Ok(())
+}
+
+fn parallelize(args: &clap::ArgMatches, source: &Source) -> AppResult<()> {
+    let format: Format = source.ext.as_str().try_into()?;
+    let graph = parse(source.input(), format)?.parallelize();
+
+    match args.get_one::<PathBuf>("output") {
+        Some(output) => std::fs::write(output, graph.to_string())
+            .map_err(|e| Error::RenderError(format!("Failed to write IR: {e}")))?,
+        None => println!("{graph}"),
+    }
+
+    Ok(())
+}
+
+fn critical_path(source: &Source) -> AppResult<()> {
+    let format: Format = source.ext.as_str().try_into()?;
+    let graph = parse_and_validate(source.input(), format)?;
+
+    println!("{}", graph.critical_path());
+
+    Ok(())
+}
+
+fn schedule(args: &clap::ArgMatches, source: &Source) -> AppResult<()> {
+    let format: Format = source.ext.as_str().try_into()?;
+    let graph = parse(source.input(), format)?.validate()?;
+
+    let processors = *args.get_one::<usize>("processors").unwrap();
+    let heuristic: Heuristic = args
+        .get_one::<String>("heuristic")
+        .unwrap()
+        .as_str()
+        .try_into()?;
+    let schedule = graph.schedule(processors, heuristic)?;
+    println!("{schedule}");
+
+    if let Some(output) = args.get_one::<PathBuf>("output") {
+        render_gantt_to_pdf(&schedule, output)?;
+    }
 
     Ok(())
 }
+
+fn lint(args: &clap::ArgMatches, source: &Source) -> AppResult<()> {
+    let format: Format = source.ext.as_str().try_into()?;
+    // The warnings themselves are reported before running any command.
+    let graph = parse(source.input(), format)?.validate()?;
+
+    if args.get_flag("fix") {
+        let fixed = graph.remove_redundant_dependencies();
+        match args.get_one::<PathBuf>("output") {
+            Some(output) => std::fs::write(output, fixed.to_string())
+                .map_err(|e| Error::RenderError(format!("Failed to write IR: {e}")))?,
+            None => println!("{fixed}"),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format_of(output: &str) -> AppResult<&'static str> {
+        let matches = cli::build()
+            .try_get_matches_from(["concurrent", "render", "-i", "$s0$", "-o", output])
+            .unwrap();
+        render_format(matches.subcommand_matches("render").unwrap())
+    }
+
+    fn run(program: &str) -> AppResult<()> {
+        run_simulation(&Source {
+            name: "<input>".to_string(),
+            text: program.to_string(),
+            ext: "fk".to_string(),
+            dir: PathBuf::new(),
+        })
+    }
+
+    #[test]
+    fn test_failed_simulation_is_an_error() {
+        let deadlock = "begin\nc1 := 2\ns1\nJ: join c1\ns2\ngoto _end\n_end: end\nend";
+
+        assert!(run("begin\ns1\ngoto _end\n_end: end\nend").is_ok());
+        assert!(matches!(
+            run(deadlock),
+            Err(Error::SimulationFailed(message)) if message == "1 thread(s) deadlocked"
+        ));
+    }
+
+    #[test]
+    fn test_render_format_from_extension() {
+        assert_eq!(format_of("out.png").unwrap(), "png");
+        assert_eq!(format_of("dir/out.gv").unwrap(), "dot");
+        assert_eq!(format_of("out.mmd").unwrap(), "mermaid");
+        assert_eq!(format_of("out.graph").unwrap(), "ir");
+        assert!(matches!(format_of("out.jpg"), Err(Error::InvalidType(ext)) if ext == "jpg"));
+        assert!(matches!(format_of("out"), Err(Error::InvalidType(ext)) if ext.is_empty()));
+    }
+}
@@ -0,0 +1,502 @@
+use std::collections::HashMap;
+
+use petgraph::Direction;
+use petgraph::graph::NodeIndex;
+
+use crate::Error;
+use crate::graph::{Graph, Ir, Valid, ir};
+
+/// Dependencies between the tasks of a validated graph, indexed by position.
+struct Precedence {
+    names: Vec<String>,
+    preds: Vec<Vec<usize>>,
+    /// Whether a task runs before another, directly or not.
+    before: Vec<Vec<bool>>,
+    /// The blocks of the graph, in which to look for tasks that can be
+    /// ordered apart from the rest.
+    root: Block,
+}
+
+/// A task, or the tasks of a block of the graph.
+enum Block {
+    Task(usize),
+    Group(Vec<Block>),
+}
+
+impl Block {
+    fn new(nodes: &[ir::Node], index: &HashMap<&str, usize>) -> Self {
+        Block::Group(
+            nodes
+                .iter()
+                .filter_map(|node| match node {
+                    ir::Node::Atomic(id, ..) => index.get(id.as_str()).map(|&n| Block::Task(n)),
+                    ir::Node::Dep(_) => None,
+                    ir::Node::Par(children)
+                    | ir::Node::Seq(children)
+                    | ir::Node::Loop(children)
+                    | ir::Node::Choice(children)
+                    | ir::Node::Labelled(_, children, _) => Some(Block::new(children, index)),
+                })
+                .collect(),
+        )
+    }
+
+    /// The tasks of the block that are still `left`.
+    fn tasks(&self, left: &[bool]) -> Vec<usize> {
+        match self {
+            Block::Task(n) if left[*n] => vec![*n],
+            Block::Task(_) => vec![],
+            Block::Group(children) => children.iter().flat_map(|c| c.tasks(left)).collect(),
+        }
+    }
+}
+
+impl Precedence {
+    fn new(graph: &Graph<ir::Node, Ir, Valid>) -> Self {
+        let petgraph = graph.to_petgraph();
+        let names: Vec<String> = petgraph.node_weights().cloned().collect();
+        let preds: Vec<Vec<usize>> = petgraph
+            .node_indices()
+            .map(|idx| {
+                let mut nodes: Vec<_> = petgraph
+                    .neighbors_directed(idx, Direction::Incoming)
+                    .map(|n| n.index())
+                    .collect();
+                nodes.sort_unstable();
+                nodes.dedup();
+                nodes
+            })
+            .collect();
+
+        let mut before = vec![vec![false; names.len()]; names.len()];
+        for (n, after) in before.iter_mut().enumerate() {
+            let mut pending = vec![n];
+            while let Some(task) = pending.pop() {
+                for next in petgraph.neighbors_directed(NodeIndex::new(task), Direction::Outgoing) {
+                    if !after[next.index()] {
+                        after[next.index()] = true;
+                        pending.push(next.index());
+                    }
+                }
+            }
+        }
+
+        let index = names
+            .iter()
+            .enumerate()
+            .map(|(n, name)| (name.as_str(), n))
+            .collect();
+        let root = Block::new(&graph.0, &index);
+
+        Self {
+            names,
+            preds,
+            before,
+            root,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    fn available(&self, placed: &[bool]) -> Vec<usize> {
+        (0..self.len())
+            .filter(|&n| !placed[n] && self.preds[n].iter().all(|&p| placed[p]))
+            .collect()
+    }
+
+    /// Number of orders of the tasks of `block` that are still `left`, which
+    /// every other task runs all before, all after or apart from. `None` when
+    /// it doesn't fit in a `u128`.
+    ///
+    /// Every part of the block with that same property is counted on its own
+    /// and then taken as a single sequence of tasks: sequences in series add
+    /// no orders and sequences in parallel interleave in as many ways as the
+    /// multinomial of their lengths. Only the parts the `#{}` dependencies
+    /// cross are searched task by task.
+    fn count(&self, block: &Block, left: &[bool]) -> Option<u128> {
+        let Block::Group(children) = block else {
+            return Some(1);
+        };
+        let tasks = block.tasks(left);
+
+        let mut total = 1u128;
+        let mut runs = vec![];
+        let mut parts: Vec<_> = children.iter().collect();
+        while let Some(part) = parts.pop() {
+            let members = part.tasks(left);
+            if members.is_empty() {
+                continue;
+            }
+            if self.is_module(&members, &tasks) {
+                total = total.checked_mul(self.count(part, left)?)?;
+                runs.push(members);
+            } else if let Block::Group(children) = part {
+                parts.extend(children);
+            }
+        }
+
+        total.checked_mul(self.interleavings(&runs)?)
+    }
+
+    /// Whether every task of `tasks` outside `members` runs all before, all
+    /// after or apart from them.
+    fn is_module(&self, members: &[usize], tasks: &[usize]) -> bool {
+        tasks.iter().filter(|t| !members.contains(t)).all(|&t| {
+            let relation = |m: usize| (self.before[t][m], self.before[m][t]);
+            members.iter().all(|&m| relation(m) == relation(members[0]))
+        })
+    }
+
+    /// Ways to interleave sequences of tasks, where each one either runs all
+    /// before, all after or apart from every other.
+    fn interleavings(&self, runs: &[Vec<usize>]) -> Option<u128> {
+        let waits: Vec<Vec<usize>> = runs
+            .iter()
+            .map(|run| {
+                (0..runs.len())
+                    .filter(|&other| self.before[runs[other][0]][run[0]])
+                    .collect()
+            })
+            .collect();
+        let lengths: Vec<_> = runs.iter().map(Vec::len).collect();
+
+        if waits.iter().all(Vec::is_empty) {
+            return multinomial(&lengths);
+        }
+        if waits.iter().map(Vec::len).sum::<usize>() == runs.len() * (runs.len() - 1) / 2 {
+            return Some(1);
+        }
+        interleave(
+            &mut vec![0; runs.len()],
+            &lengths,
+            &waits,
+            &mut HashMap::new(),
+        )
+    }
+
+    fn total(&self) -> Option<u128> {
+        self.count(&self.root, &vec![true; self.len()])
+    }
+
+    fn random_order(&self, rng: &mut SplitMix64) -> Option<Vec<String>> {
+        let mut placed = vec![false; self.len()];
+        let mut order = vec![];
+
+        loop {
+            let available = self.available(&placed);
+            if available.is_empty() {
+                break;
+            }
+
+            // Weight every choice by the number of orders it leads to.
+            let weights = available
+                .iter()
+                .map(|&n| {
+                    placed[n] = true;
+                    let left: Vec<_> = placed.iter().map(|placed| !placed).collect();
+                    placed[n] = false;
+                    self.count(&self.root, &left)
+                })
+                .collect::<Option<Vec<_>>>()?;
+
+            let chosen = available[pick(rng, &weights)?];
+            placed[chosen] = true;
+            order.push(self.names[chosen].clone());
+        }
+
+        Some(order)
+    }
+}
+
+/// Ways to interleave sequences of the given lengths.
+fn multinomial(lengths: &[usize]) -> Option<u128> {
+    let mut total = 1u128;
+    let mut placed = 0u128;
+    for &length in lengths {
+        // Choose where the tasks of this sequence go among the ones so far.
+        for i in 1..=length as u128 {
+            placed += 1;
+            total = total.checked_mul(placed)? / i;
+        }
+    }
+    Some(total)
+}
+
+/// Ways to finish interleaving sequences of `lengths` once `progress` tasks
+/// of each ran, where a sequence starts after the ones it `waits` for end.
+fn interleave(
+    progress: &mut Vec<usize>,
+    lengths: &[usize],
+    waits: &[Vec<usize>],
+    memo: &mut HashMap<Vec<usize>, u128>,
+) -> Option<u128> {
+    if let Some(&count) = memo.get(progress.as_slice()) {
+        return Some(count);
+    }
+
+    let mut count = 0u128;
+    let mut finished = true;
+    for run in 0..lengths.len() {
+        if progress[run] == lengths[run] {
+            continue;
+        }
+        finished = false;
+        if waits[run].iter().any(|&w| progress[w] < lengths[w]) {
+            continue;
+        }
+        progress[run] += 1;
+        let ways = interleave(progress, lengths, waits, memo);
+        progress[run] -= 1;
+        count = count.checked_add(ways?)?;
+    }
+    if finished {
+        count = 1;
+    }
+
+    memo.insert(progress.clone(), count);
+    Some(count)
+}
+
+/// Index of `weights` picked with probability proportional to its weight.
+fn pick(rng: &mut SplitMix64, weights: &[u128]) -> Option<usize> {
+    let total = weights
+        .iter()
+        .try_fold(0u128, |acc, &w| acc.checked_add(w))?;
+    let mut pick = rng.next_u128() % total.max(1);
+
+    for (i, &weight) in weights.iter().enumerate() {
+        if pick < weight {
+            return Some(i);
+        }
+        pick -= weight;
+    }
+    Some(0)
+}
+
+fn too_many_orders() -> Error {
+    Error::Overflow("there are more execution orders than fit in 128 bits".to_string())
+}
+
+impl Graph<ir::Node, Ir, Valid> {
+    /// Every execution order allowed by the structure and the `#{}` dependencies.
+    /// Only one alternative of every choice runs, so the orders of every way
+    /// the choices can go come one after the other.
+    pub fn orders(&self) -> Orders {
+        Orders {
+            precedences: self.precedences().into_iter(),
+            search: None,
+        }
+    }
+
+    /// How many execution orders there are, without listing them.
+    pub fn count_orders(&self) -> Result<u128, Error> {
+        self.precedences()
+            .iter()
+            .try_fold(0u128, |acc, precedence| {
+                acc.checked_add(precedence.total()?)
+            })
+            .ok_or_else(too_many_orders)
+    }
+
+    /// An execution order picked uniformly at random, reproducible by `seed`.
+    pub fn random_order(&self, seed: u64) -> Result<Vec<String>, Error> {
+        let precedences = self.precedences();
+        let mut rng = SplitMix64(seed);
+
+        // Weight every way the choices can go by the number of orders it allows.
+        let chosen = if precedences.len() > 1 {
+            let weights = precedences
+                .iter()
+                .map(Precedence::total)
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(too_many_orders)?;
+            pick(&mut rng, &weights).ok_or_else(too_many_orders)?
+        } else {
+            0
+        };
+        precedences[chosen]
+            .random_order(&mut rng)
+            .ok_or_else(too_many_orders)
+    }
+
+    /// The precedence of every way the choices can go.
+    fn precedences(&self) -> Vec<Precedence> {
+        ir::resolutions(&self.0)
+            .into_iter()
+            .map(|nodes| Precedence::new(&Graph::new(nodes)))
+            .collect()
+    }
+}
+
+/// Lazy enumeration of the execution orders, see [`Graph::orders`].
+pub struct Orders {
+    precedences: std::vec::IntoIter<Precedence>,
+    search: Option<Search>,
+}
+
+impl Iterator for Orders {
+    type Item = Vec<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(order) = self.search.as_mut().and_then(Search::next) {
+                return Some(order);
+            }
+            self.search = Some(Search::new(self.precedences.next()?));
+        }
+    }
+}
+
+/// Enumeration of the orders of a single precedence.
+struct Search {
+    precedence: Precedence,
+    placed: Vec<bool>,
+    order: Vec<usize>,
+    /// Candidates at every depth of the search and the next one to try.
+    frames: Vec<(Vec<usize>, usize)>,
+}
+
+impl Search {
+    fn new(precedence: Precedence) -> Self {
+        let placed = vec![false; precedence.len()];
+        let first = precedence.available(&placed);
+        let frames = if first.is_empty() {
+            vec![]
+        } else {
+            vec![(first, 0)]
+        };
+
+        Self {
+            precedence,
+            placed,
+            order: vec![],
+            frames,
+        }
+    }
+}
+
+impl Iterator for Search {
+    type Item = Vec<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let depth = self.frames.len();
+            let (candidates, next) = self.frames.last_mut()?;
+
+            // Undo the choice made the last time we were at this depth.
+            if self.order.len() == depth {
+                let last = self.order.pop().unwrap();
+                self.placed[last] = false;
+            }
+
+            if *next == candidates.len() {
+                self.frames.pop();
+                continue;
+            }
+
+            let chosen = candidates[*next];
+            *next += 1;
+            self.placed[chosen] = true;
+            self.order.push(chosen);
+
+            if self.order.len() == self.precedence.len() {
+                return Some(
+                    self.order
+                        .iter()
+                        .map(|&n| self.precedence.names[n].clone())
+                        .collect(),
+                );
+            }
+
+            let available = self.precedence.available(&self.placed);
+            self.frames.push((available, 0));
+        }
+    }
+}
+
+/// Small deterministic generator, enough to sample orders reproducibly.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn next_u128(&mut self) -> u128 {
+        (u128::from(self.next_u64()) << 64) | u128::from(self.next_u64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(input: &str) -> Graph<ir::Node, Ir, Valid> {
+        Graph::new(ir::parse(input).unwrap().0)
+    }
+
+    #[test]
+    fn test_orders_follow_the_structure() {
+        let orders: Vec<_> = graph("$s0,{s1,[s2,s3]},s4$")
+            .orders()
+            .map(|order| order.join(" "))
+            .collect();
+
+        assert_eq!(
+            orders,
+            ["s0 s1 s2 s3 s4", "s0 s2 s1 s3 s4", "s0 s2 s3 s1 s4"]
+        );
+    }
+
+    #[test]
+    fn test_count_matches_the_orders() {
+        for input in [
+            "$s0,{[s1,s2],[s3,s4#{s1}]},s5$",
+            "$s0,{[s1,s2#{s4},s3],[s4,s7,s8],s5},s6$",
+            "$s0,s1,{[s2,s5#{s3},s8#{s6}],[s3,s6!],[s4,s7,s9#{s6}]},sa$",
+            "$s0,(s1|{s2,s3}),s4$",
+        ] {
+            let graph = graph(input);
+            assert_eq!(
+                graph.count_orders().unwrap(),
+                graph.orders().count() as u128
+            );
+        }
+    }
+
+    #[test]
+    fn test_count_large_parallel_blocks() {
+        let graph = graph(
+            "$s0,{[a1,a2,a3,a4,a5,a6],[b1,b2,b3,b4,b5,b6],[c1,c2,c3,c4,c5,c6],[d1,d2,d3,d4,d5,d6]}$",
+        );
+
+        // 24! / 6!^4
+        assert_eq!(graph.count_orders().unwrap(), 2_308_743_493_056);
+    }
+
+    #[test]
+    fn test_count_overflow_is_reported() {
+        let tasks: Vec<_> = (0..40).map(|n| format!("s{n}")).collect();
+        let graph = graph(&format!("${{{}}}$", tasks.join(",")));
+
+        assert!(matches!(graph.count_orders(), Err(Error::Overflow(_))));
+    }
+
+    #[test]
+    fn test_random_order_is_valid_and_reproducible() {
+        let graph = graph("$s0,{[s1,s2],[s3,s4#{s1}]},s5$");
+        let orders: Vec<_> = graph.orders().collect();
+
+        for seed in 0..20 {
+            let order = graph.random_order(seed).unwrap();
+            assert!(orders.contains(&order));
+            assert_eq!(order, graph.random_order(seed).unwrap());
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+
+use layout::core::color::Color;
+use layout::core::format::{ClipHandle, RenderBackend};
+use layout::core::geometry::Point;
+use layout::core::style::StyleAttr;
+use layout::std_shapes::shapes::ShapeKind;
+use layout::topo::layout::VisualGraph;
+
+use crate::graph::ir;
+
+/// Space between a box and what's inside it.
+const PADDING: f64 = 6.0;
+const LABEL_SIZE: usize = 10;
+const PAR_COLOR: &str = "steelblue";
+const SEQ_COLOR: &str = "gray";
+
+/// Moves everything drawn by `inner`, making room for the boxes around the
+/// tasks on the top and left sides.
+pub struct Shifted<'a> {
+    pub inner: &'a mut dyn RenderBackend,
+    pub offset: Point,
+}
+
+impl RenderBackend for Shifted<'_> {
+    fn draw_rect(
+        &mut self,
+        xy: Point,
+        size: Point,
+        look: &StyleAttr,
+        properties: Option<String>,
+        clip: Option<ClipHandle>,
+    ) {
+        self.inner
+            .draw_rect(xy.add(self.offset), size, look, properties, clip);
+    }
+
+    fn draw_line(
+        &mut self,
+        start: Point,
+        stop: Point,
+        look: &StyleAttr,
+        properties: Option<String>,
+    ) {
+        self.inner.draw_line(
+            start.add(self.offset),
+            stop.add(self.offset),
+            look,
+            properties,
+        );
+    }
+
+    fn draw_circle(
+        &mut self,
+        xy: Point,
+        size: Point,
+        look: &StyleAttr,
+        properties: Option<String>,
+    ) {
+        self.inner
+            .draw_circle(xy.add(self.offset), size, look, properties);
+    }
+
+    fn draw_text(&mut self, xy: Point, text: &str, look: &StyleAttr) {
+        self.inner.draw_text(xy.add(self.offset), text, look);
+    }
+
+    fn draw_arrow(
+        &mut self,
+        path: &[(Point, Point)],
+        dashed: bool,
+        head: (bool, bool),
+        look: &StyleAttr,
+        properties: Option<String>,
+        text: &str,
+    ) {
+        let path: Vec<_> = path
+            .iter()
+            .map(|(a, b)| (a.add(self.offset), b.add(self.offset)))
+            .collect();
+        self.inner
+            .draw_arrow(&path, dashed, head, look, properties, text);
+    }
+
+    fn create_clip(&mut self, xy: Point, size: Point, rounded_px: usize) -> ClipHandle {
+        self.inner
+            .create_clip(xy.add(self.offset), size, rounded_px)
+    }
+}
+
+/// How much room the boxes of the outermost blocks take around their tasks.
+pub fn margin(nodes: &[ir::Node]) -> f64 {
+    fn depth(nodes: &[ir::Node]) -> usize {
+        nodes
+            .iter()
+            .map(|node| match node {
+                ir::Node::Atomic(..) | ir::Node::Dep(_) => 0,
+                ir::Node::Par(children)
+                | ir::Node::Seq(children)
+                | ir::Node::Loop(children)
+                | ir::Node::Choice(children)
+                | ir::Node::Labelled(_, children, _) => 1 + depth(children),
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    depth(nodes) as f64 * (PADDING + LABEL_SIZE as f64) + PADDING
+}
+
+/// Draws a labelled box around the tasks of every block, once `graph` has
+/// been laid out.
+pub fn draw_clusters(nodes: &[ir::Node], graph: &VisualGraph, rb: &mut dyn RenderBackend) {
+    let mut bounds = HashMap::new();
+    for handle in graph.iter_nodes() {
+        let element = graph.element(handle);
+        if let ShapeKind::Box(name) | ShapeKind::Circle(name) | ShapeKind::DoubleCircle(name) =
+            &element.shape
+        {
+            bounds.insert(name.as_str(), element.pos.bbox(false));
+        }
+    }
+
+    for node in nodes {
+        draw_cluster(node, &bounds, rb);
+    }
+}
+
+/// Draws the boxes of `node` and the blocks inside it, returning the area
+/// they take.
+fn draw_cluster(
+    node: &ir::Node,
+    bounds: &HashMap<&str, (Point, Point)>,
+    rb: &mut dyn RenderBackend,
+) -> Option<(Point, Point)> {
+    let (label, color, children) = match node {
+        ir::Node::Atomic(id, ..) => return bounds.get(id.as_str()).copied(),
+        ir::Node::Dep(_) => return None,
+        ir::Node::Par(children) => ("par".to_string(), PAR_COLOR, children),
+        ir::Node::Choice(children) => ("choice".to_string(), PAR_COLOR, children),
+        ir::Node::Seq(children) => ("seq".to_string(), SEQ_COLOR, children),
+        ir::Node::Loop(children) => ("loop".to_string(), SEQ_COLOR, children),
+        ir::Node::Labelled(name, children, _) => (format!("@{name}"), SEQ_COLOR, children),
+    };
+
+    let (mut top_left, mut bottom_right) = children
+        .iter()
+        .filter_map(|child| draw_cluster(child, bounds, rb))
+        .reduce(|(a0, a1), (b0, b1)| {
+            (
+                Point::new(a0.x.min(b0.x), a0.y.min(b0.y)),
+                Point::new(a1.x.max(b1.x), a1.y.max(b1.y)),
+            )
+        })?;
+    top_left = top_left.sub(Point::new(PADDING, PADDING + LABEL_SIZE as f64));
+    bottom_right = bottom_right.add(Point::splat(PADDING));
+
+    let look = StyleAttr::new(Color::fast(color), 1, None, 6, LABEL_SIZE);
+    rb.draw_rect(top_left, bottom_right.sub(top_left), &look, None, None);
+    let width = label.len() as f64 * LABEL_SIZE as f64 * 0.6;
+    let label_at = top_left.add(Point::new(
+        PADDING + width / 2.0,
+        (PADDING + LABEL_SIZE as f64) / 2.0,
+    ));
+    rb.draw_text(label_at, &label, &look);
+
+    Some((top_left, bottom_right))
+}
@@ -0,0 +1,111 @@
+use std::fmt::Write;
+
+use petgraph::visit::EdgeRef;
+
+use super::{CRITICAL_STYLE, Flow, edge_style};
+use crate::critical_path::CriticalPath;
+use crate::graph::ir;
+
+const TASK_SHAPE: &str = "box";
+const TERMINAL_SHAPE: &str = "doubleoctagon";
+
+/// Writes the graph as DOT for Graphviz.
+///
+/// Every block of `nodes` becomes a `subgraph cluster_*` holding its tasks,
+/// terminal tasks are drawn as octagons and the `#{}` dependencies dashed.
+/// The edges are the ones of `graph`, the petgraph built from `nodes`.
+pub fn render_dot(nodes: &[ir::Node], graph: &Flow, critical: Option<&CriticalPath>) -> String {
+    let mut dot = String::from("digraph {\n");
+    let mut clusters = 0;
+    write_nodes(&mut dot, nodes, critical, &mut clusters, 1);
+
+    for edge in graph.edge_references() {
+        // The dashed `#{}` edge already joins both tasks.
+        let shadowed = *edge.weight() != "dep"
+            && graph
+                .edges_connecting(edge.source(), edge.target())
+                .any(|other| *other.weight() == "dep");
+        if shadowed {
+            continue;
+        }
+        let (from, to) = (&graph[edge.source()], &graph[edge.target()]);
+        let style = edge_style(graph, edge, critical);
+        if style.is_empty() {
+            writeln!(dot, "    {from:?} -> {to:?};").unwrap();
+        } else {
+            writeln!(dot, "    {from:?} -> {to:?} [{style}];").unwrap();
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+fn write_nodes(
+    dot: &mut String,
+    nodes: &[ir::Node],
+    critical: Option<&CriticalPath>,
+    clusters: &mut usize,
+    depth: usize,
+) {
+    let indent = "    ".repeat(depth);
+    for node in nodes {
+        let (label, children) = match node {
+            ir::Node::Atomic(id, _, terminal, _) => {
+                let shape = if *terminal {
+                    TERMINAL_SHAPE
+                } else {
+                    TASK_SHAPE
+                };
+                let style = match critical {
+                    Some(critical) if critical.is_critical(id) => format!(", {CRITICAL_STYLE}"),
+                    _ => String::new(),
+                };
+                writeln!(dot, "{indent}{id:?} [shape = {shape}{style}];").unwrap();
+                continue;
+            }
+            ir::Node::Par(children) => ("par".to_string(), children),
+            ir::Node::Seq(children) => ("seq".to_string(), children),
+            ir::Node::Loop(children) => ("loop".to_string(), children),
+            ir::Node::Choice(children) => ("choice".to_string(), children),
+            ir::Node::Labelled(name, children, _) => (format!("@{name}"), children),
+            ir::Node::Dep(_) => continue,
+        };
+
+        *clusters += 1;
+        writeln!(dot, "{indent}subgraph cluster_{clusters} {{").unwrap();
+        writeln!(dot, "{indent}    label = {label:?};").unwrap();
+        write_nodes(dot, children, critical, clusters, depth + 1);
+        writeln!(dot, "{indent}}}").unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{Graph, Ir, Valid};
+    use crate::render::DEP_STYLE;
+
+    #[test]
+    fn test_blocks_are_clusters_and_dependencies_dashed() {
+        let graph =
+            Graph::<ir::Node, Ir, Valid>::new(ir::parse("$s0,{[s1,s2],s3},s4#{s1}!$").unwrap().0);
+        let dot = render_dot(&graph.0, &graph.to_petgraph_with_loops(), None);
+
+        assert!(dot.contains("subgraph cluster_1 {\n        label = \"par\";"));
+        assert!(dot.contains("subgraph cluster_2 {\n            label = \"seq\";"));
+        assert!(dot.contains("\"s4\" [shape = doubleoctagon];"));
+        assert!(dot.contains(&format!("\"s1\" -> \"s4\" [{DEP_STYLE}];")));
+        assert!(dot.contains("\"s0\" -> \"s1\";"));
+    }
+
+    #[test]
+    fn test_dependency_replaces_the_structural_edge() {
+        let graph = Graph::<ir::Node, Ir, Valid>::new(ir::parse("$s0,s1#{s0},s2$").unwrap().0);
+        let dot = render_dot(&graph.0, &graph.to_petgraph_with_loops(), None);
+
+        assert_eq!(dot.matches("\"s0\" -> \"s1\"").count(), 1);
+        assert!(dot.contains(&format!("\"s0\" -> \"s1\" [{DEP_STYLE}];")));
+        assert!(dot.contains("\"s1\" -> \"s2\";"));
+    }
+}
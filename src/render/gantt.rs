@@ -0,0 +1,94 @@
+use std::fmt::Write;
+
+use crate::schedule::Schedule;
+
+const UNIT: f64 = 40.0;
+const ROW: f64 = 36.0;
+const MARGIN: f64 = 20.0;
+const LABEL_WIDTH: f64 = 40.0;
+
+/// Draws the schedule as a Gantt chart: one row per processor, one box per task.
+pub fn render_gantt(schedule: &Schedule) -> String {
+    let makespan = schedule.makespan.max(1) as f64;
+    let chart_width = makespan * UNIT;
+    let chart_height = schedule.processors as f64 * ROW;
+    let width = MARGIN * 2.0 + LABEL_WIDTH + chart_width;
+    let height = MARGIN * 3.0 + chart_height + 20.0;
+    let left = MARGIN + LABEL_WIDTH;
+
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"<rect x="0" y="0" width="{width}" height="{height}" fill="white"/>"#
+    )
+    .unwrap();
+
+    for processor in 0..schedule.processors {
+        let y = MARGIN + processor as f64 * ROW;
+        writeln!(
+            svg,
+            r#"<text x="{MARGIN}" y="{}" font-family="sans-serif" font-size="14">P{processor}</text>"#,
+            y + ROW / 2.0 + 5.0
+        )
+        .unwrap();
+    }
+
+    for tick in 0..=schedule.makespan {
+        let x = left + tick as f64 * UNIT;
+        writeln!(
+            svg,
+            r##"<line x1="{x}" y1="{MARGIN}" x2="{x}" y2="{}" stroke="#dddddd"/>"##,
+            MARGIN + chart_height
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            r#"<text x="{x}" y="{}" font-family="sans-serif" font-size="12" text-anchor="middle">{tick}</text>"#,
+            MARGIN * 2.0 + chart_height
+        )
+        .unwrap();
+    }
+
+    for slot in &schedule.slots {
+        let x = left + slot.start as f64 * UNIT;
+        let y = MARGIN + slot.processor as f64 * ROW + 4.0;
+        let w = (slot.finish - slot.start) as f64 * UNIT;
+        let (fill, stroke) = if slot.critical {
+            ("#ffd6d6", "#ff0000")
+        } else {
+            ("#dde8ff", "#000000")
+        };
+        writeln!(
+            svg,
+            r#"<rect x="{x}" y="{y}" width="{w}" height="{}" fill="{fill}" stroke="{stroke}"/>"#,
+            ROW - 8.0
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            r#"<text x="{}" y="{}" font-family="sans-serif" font-size="13" text-anchor="middle">{}</text>"#,
+            x + w / 2.0,
+            y + ROW / 2.0 + 1.0,
+            slot.task
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        svg,
+        r#"<text x="{left}" y="{}" font-family="sans-serif" font-size="12">{} on {} processors, makespan {}, speed-up {:.2}</text>"#,
+        height - MARGIN / 2.0,
+        schedule.heuristic,
+        schedule.processors,
+        schedule.makespan,
+        schedule.speedup()
+    )
+    .unwrap();
+    svg.push_str("</svg>\n");
+    svg
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+
+use super::Flow;
+use crate::critical_path::CriticalPath;
+use crate::graph::ir;
+
+/// Writes the graph as a Mermaid `flowchart TD`.
+///
+/// Tasks are named `t0`, `t1`, ... since Mermaid ids can't hold the `.` and
+/// `#` of block copies. Parallel, labelled, looping and choice blocks become
+/// subgraphs, and the `#{}` dependencies dotted arrows.
+pub fn render_mermaid(nodes: &[ir::Node], graph: &Flow, critical: Option<&CriticalPath>) -> String {
+    let ids: HashMap<_, _> = graph
+        .node_indices()
+        .map(|idx| (graph[idx].as_str(), idx))
+        .collect();
+
+    let mut mermaid = String::from("flowchart TD\n");
+    let mut subgraphs = 0;
+    write_nodes(&mut mermaid, nodes, &ids, &mut subgraphs, 1);
+
+    let mut critical_edges = vec![];
+    for (i, edge) in graph.edge_references().enumerate() {
+        let (from, to) = (edge.source().index(), edge.target().index());
+        let arrow = match *edge.weight() {
+            "dep" => "-.->",
+            "loop" => "-. repeat .->",
+            "choice" => "-->|or|",
+            _ => "-->",
+        };
+        writeln!(mermaid, "    t{from} {arrow} t{to}").unwrap();
+
+        let (from, to) = (&graph[edge.source()], &graph[edge.target()]);
+        if critical.is_some_and(|critical| critical.is_critical_edge(from, to)) {
+            critical_edges.push(i.to_string());
+        }
+    }
+
+    if let Some(critical) = critical {
+        let tasks: Vec<_> = graph
+            .node_indices()
+            .filter(|&idx| critical.is_critical(&graph[idx]))
+            .map(|idx| format!("t{}", idx.index()))
+            .collect();
+        mermaid.push_str("    classDef critical stroke:red,stroke-width:2px\n");
+        if !tasks.is_empty() {
+            writeln!(mermaid, "    class {} critical", tasks.join(",")).unwrap();
+        }
+        if !critical_edges.is_empty() {
+            writeln!(
+                mermaid,
+                "    linkStyle {} stroke:red,stroke-width:2px",
+                critical_edges.join(",")
+            )
+            .unwrap();
+        }
+    }
+
+    mermaid
+}
+
+fn write_nodes(
+    mermaid: &mut String,
+    nodes: &[ir::Node],
+    ids: &HashMap<&str, NodeIndex>,
+    subgraphs: &mut usize,
+    depth: usize,
+) {
+    let indent = "    ".repeat(depth);
+    for node in nodes {
+        let (title, children) = match node {
+            ir::Node::Atomic(id, _, terminal, _) => {
+                let idx = ids[id.as_str()].index();
+                // `#` starts an entity in Mermaid labels.
+                let label = id.replace('#', "#35;");
+                if *terminal {
+                    writeln!(mermaid, "{indent}t{idx}(((\"{label}\")))").unwrap();
+                } else {
+                    writeln!(mermaid, "{indent}t{idx}[\"{label}\"]").unwrap();
+                }
+                continue;
+            }
+            // Sequences read fine as a column of arrows.
+            ir::Node::Seq(children) => {
+                write_nodes(mermaid, children, ids, subgraphs, depth);
+                continue;
+            }
+            ir::Node::Par(children) => ("par".to_string(), children),
+            ir::Node::Loop(children) => ("loop".to_string(), children),
+            ir::Node::Choice(children) => ("choice".to_string(), children),
+            ir::Node::Labelled(name, children, _) => (format!("@{name}"), children),
+            ir::Node::Dep(_) => continue,
+        };
+
+        *subgraphs += 1;
+        writeln!(mermaid, "{indent}subgraph b{subgraphs} [\"{title}\"]").unwrap();
+        write_nodes(mermaid, children, ids, subgraphs, depth + 1);
+        writeln!(mermaid, "{indent}end").unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{Graph, Ir, Valid};
+
+    #[test]
+    fn test_parallel_blocks_are_subgraphs() {
+        let graph =
+            Graph::<ir::Node, Ir, Valid>::new(ir::parse("$s0,{[s1,s2],s3},s4#{s1}$").unwrap().0);
+        let mermaid = render_mermaid(&graph.0, &graph.to_petgraph_with_loops(), None);
+
+        assert_eq!(
+            mermaid,
+            "flowchart TD\n    t0[\"s0\"]\n    subgraph b1 [\"par\"]\n        t1[\"s1\"]\n        \
+             t2[\"s2\"]\n        t3[\"s3\"]\n    end\n    t4[\"s4\"]\n    t0 --> t1\n    \
+             t1 --> t2\n    t0 --> t3\n    t2 --> t4\n    t3 --> t4\n    t1 -.-> t4\n"
+        );
+    }
+}
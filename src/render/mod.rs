@@ -1,41 +1,101 @@
+mod clusters;
+mod dot;
+mod gantt;
+mod mermaid;
+mod text;
+
 use std::fmt::Write;
 use std::io;
 use std::path::Path;
 
+use clusters::Shifted;
 use layout::backends::svg::SVGWriter;
+use layout::core::geometry::Point;
 use layout::gv::{DotParser, GraphBuilder};
 use layout::topo::layout::VisualGraph;
 use petgraph::Directed;
 use petgraph::dot::{Config, Dot};
-use petgraph::graph::Graph as PetGraph;
+use petgraph::graph::{EdgeReference, Graph as PetGraph};
+use petgraph::visit::EdgeRef;
+
+use crate::critical_path::CriticalPath;
+use crate::graph::ir;
+
+pub use dot::render_dot;
+pub use gantt::render_gantt;
+pub use mermaid::render_mermaid;
+pub use text::{TextStyle, render_text};
 
 pub type Flow = PetGraph<String, &'static str, Directed>;
 
-pub fn render_graph(graph: &Flow) -> String {
+const CRITICAL_STYLE: &str = "color = red, penwidth = 2";
+const LOOP_STYLE: &str = "color = blue, style = dashed, label = \"repeat\"";
+const CHOICE_STYLE: &str = "color = darkorange, label = \"or\"";
+const DEP_STYLE: &str = "style = dashed, color = purple";
+
+/// Largest PNG drawn, in pixels, so a huge `--scale` fails instead of
+/// taking all the memory.
+const MAX_PIXELS: u64 = 100_000_000;
+
+/// Writes the graph as DOT, drawing the tasks and edges of `critical` in red,
+/// the back-edges of loops dashed, the edges into the alternatives of a
+/// choice in orange and the `#{}` dependencies dashed in purple.
+pub fn render_graph(graph: &Flow, critical: Option<&CriticalPath>) -> String {
+    let edge_attrs = |g: &Flow, edge| edge_style(g, edge, critical);
+    let node_attrs = |_: &Flow, (_, name): (petgraph::graph::NodeIndex, &String)| match critical {
+        Some(critical) if critical.is_critical(name) => CRITICAL_STYLE.to_string(),
+        _ => String::new(),
+    };
+
     let mut buffer = String::new();
     write!(
         &mut buffer,
         "{}",
-        Dot::with_config(graph, &[Config::EdgeNoLabel])
+        Dot::with_attr_getters(graph, &[Config::EdgeNoLabel], &edge_attrs, &node_attrs)
     )
     .unwrap();
     buffer
 }
 
-pub fn render_to_svg(graph: &Flow) -> String {
-    let dot_string = render_graph(graph);
+/// DOT attributes of an edge of `graph`, empty for a plain precedence edge.
+fn edge_style(
+    graph: &Flow,
+    edge: EdgeReference<'_, &'static str>,
+    critical: Option<&CriticalPath>,
+) -> String {
+    let (from, to) = (&graph[edge.source()], &graph[edge.target()]);
+    let is_critical = critical.is_some_and(|critical| critical.is_critical_edge(from, to));
+    match *edge.weight() {
+        "loop" => LOOP_STYLE.to_string(),
+        "choice" => CHOICE_STYLE.to_string(),
+        "dep" if is_critical => format!("{DEP_STYLE}, {CRITICAL_STYLE}"),
+        "dep" => DEP_STYLE.to_string(),
+        _ if is_critical => CRITICAL_STYLE.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Lays out the graph built from `nodes` as an SVG, with a box around the
+/// tasks of every block.
+pub fn render_to_svg(nodes: &[ir::Node], graph: &Flow, critical: Option<&CriticalPath>) -> String {
+    let dot_string = render_graph(graph, critical);
     let mut parser = DotParser::new(&dot_string);
 
     let tree = parser.process().expect("Unable to parse the file");
     let mut gb = GraphBuilder::new();
     gb.visit_graph(&tree);
     let mut visual_graph = gb.get();
-    generate_svg(&mut visual_graph)
+    generate_svg(nodes, &mut visual_graph)
 }
 
-fn generate_svg(graph: &mut VisualGraph) -> String {
+fn generate_svg(nodes: &[ir::Node], graph: &mut VisualGraph) -> String {
     let mut svg = SVGWriter::new();
-    graph.do_it(false, false, false, &mut svg);
+    let mut shifted = Shifted {
+        inner: &mut svg,
+        offset: Point::splat(clusters::margin(nodes)),
+    };
+    graph.do_it(false, false, false, &mut shifted);
+    clusters::draw_clusters(nodes, graph, &mut shifted);
     svg.finalize()
 }
 
@@ -45,10 +105,108 @@ pub fn render_svg_to_pdf(svg: impl AsRef<str>, output: &Path) -> io::Result<()>
     let output = output.with_extension("pdf");
 
     let mut options = svg2pdf::usvg::Options::default();
-    options.fontdb_mut().load_system_fonts();
+    load_fonts(options.fontdb_mut());
     let tree = svg2pdf::usvg::Tree::from_str(svg.as_ref(), &options).unwrap();
 
     let pdf = svg2pdf::to_pdf(&tree, ConversionOptions::default(), PageOptions::default()).unwrap();
     std::fs::write(output, pdf)?;
     Ok(())
 }
+
+/// Rasterises the SVG to a PNG, `scale` times its size in pixels.
+pub fn render_svg_to_png(svg: impl AsRef<str>, output: &Path, scale: f32) -> io::Result<()> {
+    use resvg::{tiny_skia, usvg};
+
+    let output = output.with_extension("png");
+
+    let mut options = usvg::Options::default();
+    load_fonts(options.fontdb_mut());
+    let tree = usvg::Tree::from_str(svg.as_ref(), &options).map_err(io::Error::other)?;
+
+    let size = tree
+        .size()
+        .to_int_size()
+        .scale_by(scale)
+        .ok_or_else(|| io::Error::other(format!("can't scale the image by {scale}")))?;
+    if u64::from(size.width()) * u64::from(size.height()) > MAX_PIXELS {
+        return Err(io::Error::other(format!(
+            "a {}x{} image is too large, use a smaller scale",
+            size.width(),
+            size.height()
+        )));
+    }
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or_else(|| io::Error::other("the image is empty"))?;
+    pixmap.fill(tiny_skia::Color::WHITE);
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    pixmap.save_png(output).map_err(io::Error::other)
+}
+
+/// Loads the system fonts, falling back to any of them for the `serif` the
+/// graphs are written in when there's no Times to draw it.
+fn load_fonts(fontdb: &mut resvg::usvg::fontdb::Database) {
+    use resvg::usvg::fontdb::{Family, Query};
+
+    fontdb.load_system_fonts();
+    let query = Query {
+        families: &[Family::Serif],
+        ..Query::default()
+    };
+    if fontdb.query(&query).is_some() {
+        return;
+    }
+    let fallback = fontdb
+        .faces()
+        .find_map(|face| face.families.first())
+        .map(|(family, _)| family.clone());
+    if let Some(family) = fallback {
+        fontdb.set_serif_family(family);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{Graph, Ir, Valid};
+
+    const SVG: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10"><rect width="20" height="10" fill="black"/></svg>"#;
+
+    #[test]
+    fn test_svg_boxes_blocks_and_dashes_dependencies() {
+        let nodes = ir::parse("$s0,{[s1,s2],[s3,{s4,s5}]},s6#{s1}$").unwrap().0;
+        let graph = Graph::<ir::Node, Ir, Valid>::new(nodes);
+        let svg = render_to_svg(&graph.0, &graph.to_petgraph_with_loops(), None);
+
+        // Tasks are ellipses, so every rectangle is the box of a block.
+        assert_eq!(svg.matches("<rect").count(), 4);
+        let dashed: Vec<_> = svg
+            .lines()
+            .filter(|line| line.contains("stroke-dasharray"))
+            .collect();
+        assert_eq!(dashed.len(), 1);
+        assert!(dashed[0].contains("#800080ff"));
+    }
+
+    #[test]
+    fn test_png_is_scaled() {
+        let dir = std::env::temp_dir().join(format!("concurrent-png-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("out.png");
+
+        render_svg_to_png(SVG, &output, 2.0).unwrap();
+        let png = std::fs::read(&output).unwrap();
+        let too_large = render_svg_to_png(SVG, &dir.join("large.png"), 10_000.0);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // The size is in the IHDR chunk, right after the signature.
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(u32::from_be_bytes(png[16..20].try_into().unwrap()), 40);
+        assert_eq!(u32::from_be_bytes(png[20..24].try_into().unwrap()), 20);
+        assert!(too_large.unwrap_err().to_string().contains("too large"));
+    }
+}
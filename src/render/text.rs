@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+
+use petgraph::algo::toposort;
+use petgraph::graph::NodeIndex;
+use petgraph::visit::{EdgeFiltered, EdgeRef};
+
+use super::Flow;
+use crate::critical_path::CriticalPath;
+
+const UP: u8 = 1;
+const DOWN: u8 = 2;
+const LEFT: u8 = 4;
+const RIGHT: u8 = 8;
+
+/// Columns between two boxes of a layer.
+const GAP: usize = 2;
+/// Passes of the barycenter heuristic, each one down and back up.
+const SWEEPS: usize = 4;
+
+/// How to draw the graph in the terminal.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TextStyle {
+    /// Plain ASCII instead of box-drawing characters.
+    pub ascii: bool,
+    /// Just the tasks of every layer, one line each.
+    pub compact: bool,
+}
+
+/// Something placed on a layer: a task, or the point where an edge that skips
+/// layers goes through this one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Item {
+    Task(NodeIndex),
+    Dummy(usize),
+}
+
+/// An edge between two consecutive layers.
+struct Link {
+    from: Item,
+    to: Item,
+    dashed: bool,
+    choice: bool,
+}
+
+/// Draws the graph as layers of boxes joined by arrows, Sugiyama style: tasks
+/// go on the layer after their last predecessor, edges that skip layers go
+/// through the ones in between and every layer is ordered by the average
+/// position of its neighbours to avoid crossings.
+///
+/// `#{}` dependencies are dashed, the alternatives of a choice get a hollow
+/// arrowhead and the back-edges of loops are listed below the drawing.
+pub fn render_text(graph: &Flow, critical: Option<&CriticalPath>, style: TextStyle) -> String {
+    let forward = EdgeFiltered::from_fn(graph, |edge| *edge.weight() != "loop");
+    // Without the back-edges of loops, validated graphs are acyclic.
+    let order = toposort(&forward, None).unwrap_or_default();
+
+    let mut layer_of = HashMap::new();
+    for &idx in &order {
+        let layer = graph
+            .edges_directed(idx, petgraph::Direction::Incoming)
+            .filter(|edge| *edge.weight() != "loop")
+            .map(|edge| layer_of[&edge.source()] + 1)
+            .max()
+            .unwrap_or(0);
+        layer_of.insert(idx, layer);
+    }
+
+    let depth = layer_of.values().max().map_or(0, |max| max + 1);
+    let mut layers = vec![vec![]; depth];
+    for idx in graph.node_indices() {
+        layers[layer_of[&idx]].push(Item::Task(idx));
+    }
+
+    // A pair linked by the order and by a `#{}` too is drawn once, solid.
+    let mut edges: Vec<(NodeIndex, NodeIndex, bool, bool)> = vec![];
+    for edge in graph.edge_references() {
+        if *edge.weight() == "loop" {
+            continue;
+        }
+        let (source, target) = (edge.source(), edge.target());
+        let dashed = *edge.weight() == "dep";
+        let choice = *edge.weight() == "choice";
+        match edges
+            .iter_mut()
+            .find(|(s, t, ..)| (*s, *t) == (source, target))
+        {
+            Some(existing) => {
+                existing.2 &= dashed;
+                existing.3 |= choice;
+            }
+            None => edges.push((source, target, dashed, choice)),
+        }
+    }
+
+    let mut links = vec![];
+    let mut dummies = 0;
+    for (source, target, dashed, choice) in edges {
+        let mut from = Item::Task(source);
+        let skipped = layer_of[&source] + 1..layer_of[&target];
+        for layer in &mut layers[skipped] {
+            let dummy = Item::Dummy(dummies);
+            dummies += 1;
+            layer.push(dummy);
+            links.push(Link {
+                from,
+                to: dummy,
+                dashed,
+                choice: false,
+            });
+            from = dummy;
+        }
+        links.push(Link {
+            from,
+            to: Item::Task(target),
+            dashed,
+            choice,
+        });
+    }
+
+    order_layers(&mut layers, &links);
+
+    let label = |idx: NodeIndex| match critical {
+        Some(critical) if critical.is_critical(&graph[idx]) => format!("{}*", graph[idx]),
+        _ => graph[idx].clone(),
+    };
+
+    let mut text = if style.compact {
+        let bar = if style.ascii { '|' } else { '│' };
+        layers
+            .iter()
+            .enumerate()
+            .map(|(i, layer)| {
+                let tasks: Vec<_> = layer
+                    .iter()
+                    .filter_map(|item| match item {
+                        Item::Task(idx) => Some(label(*idx)),
+                        Item::Dummy(_) => None,
+                    })
+                    .collect();
+                format!("{i} {bar} {}\n", tasks.join(" "))
+            })
+            .collect()
+    } else {
+        draw(&layers, &links, &label, style.ascii)
+    };
+
+    let arrow = if style.ascii { "->" } else { "→" };
+    let loops: Vec<_> = graph
+        .edge_references()
+        .filter(|edge| *edge.weight() == "loop")
+        .collect();
+    if !loops.is_empty() {
+        text.push('\n');
+    }
+    for edge in loops {
+        let (from, to) = (&graph[edge.source()], &graph[edge.target()]);
+        text.push_str(&format!("{from} {arrow} {to} (repeat)\n"));
+    }
+
+    text
+}
+
+/// Reorders every layer by the average position of the items linked to it on
+/// the layer above, then on the layer below.
+fn order_layers(layers: &mut [Vec<Item>], links: &[Link]) {
+    for _ in 0..SWEEPS {
+        for i in 1..layers.len() {
+            let (above, below) = layers.split_at_mut(i);
+            sort_by_barycenter(&mut below[0], &above[i - 1], links, |link| {
+                (link.to, link.from)
+            });
+        }
+        for i in (0..layers.len().saturating_sub(1)).rev() {
+            let (above, below) = layers.split_at_mut(i + 1);
+            sort_by_barycenter(&mut above[i], &below[0], links, |link| (link.from, link.to));
+        }
+    }
+}
+
+fn sort_by_barycenter(
+    layer: &mut [Item],
+    fixed: &[Item],
+    links: &[Link],
+    ends: impl Fn(&Link) -> (Item, Item),
+) {
+    let mut keys: Vec<_> = layer
+        .iter()
+        .enumerate()
+        .map(|(i, &item)| {
+            let positions: Vec<_> = links
+                .iter()
+                .map(&ends)
+                .filter(|&(this, _)| this == item)
+                .filter_map(|(_, other)| fixed.iter().position(|&f| f == other))
+                .collect();
+            let key = if positions.is_empty() {
+                i as f64
+            } else {
+                positions.iter().sum::<usize>() as f64 / positions.len() as f64
+            };
+            (key, item)
+        })
+        .collect();
+    keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+    for (slot, (_, item)) in layer.iter_mut().zip(keys) {
+        *slot = item;
+    }
+}
+
+/// Lines through every cell, merged into the character that joins them.
+struct Canvas {
+    cells: Vec<Vec<Cell>>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Cell {
+    lines: u8,
+    /// Whether a solid line goes through, which wins over dashed ones.
+    solid: bool,
+    text: Option<char>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            cells: vec![vec![Cell::default(); width]; height],
+        }
+    }
+
+    fn mark(&mut self, x: usize, y: usize, lines: u8, dashed: bool) {
+        let cell = &mut self.cells[y][x];
+        cell.lines |= lines;
+        cell.solid |= !dashed;
+    }
+
+    fn horizontal(&mut self, y: usize, x0: usize, x1: usize, dashed: bool) {
+        let (x0, x1) = (x0.min(x1), x0.max(x1));
+        for x in x0..x1 {
+            self.mark(x, y, RIGHT, dashed);
+            self.mark(x + 1, y, LEFT, dashed);
+        }
+    }
+
+    fn vertical(&mut self, x: usize, y0: usize, y1: usize, dashed: bool) {
+        for y in y0..y1 {
+            self.mark(x, y, DOWN, dashed);
+            self.mark(x, y + 1, UP, dashed);
+        }
+    }
+
+    fn text(&mut self, x: usize, y: usize, text: &str) {
+        for (i, c) in text.chars().enumerate() {
+            self.cells[y][x + i].text = Some(c);
+        }
+    }
+
+    fn render(&self, ascii: bool) -> String {
+        let mut out = String::new();
+        for row in &self.cells {
+            let line: String = row.iter().map(|cell| cell.char(ascii)).collect();
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl Cell {
+    fn char(&self, ascii: bool) -> char {
+        if let Some(c) = self.text {
+            return c;
+        }
+        let dashed = !self.solid;
+        let vertical = self.lines & !(UP | DOWN) == 0;
+        let horizontal = self.lines & !(LEFT | RIGHT) == 0;
+        match (ascii, self.lines) {
+            (_, 0) => ' ',
+            (true, _) if vertical => {
+                if dashed {
+                    ':'
+                } else {
+                    '|'
+                }
+            }
+            (true, _) if horizontal => {
+                if dashed {
+                    '.'
+                } else {
+                    '-'
+                }
+            }
+            (true, _) => '+',
+            (false, _) if vertical => {
+                if dashed {
+                    '┆'
+                } else {
+                    '│'
+                }
+            }
+            (false, _) if horizontal => {
+                if dashed {
+                    '┄'
+                } else {
+                    '─'
+                }
+            }
+            (false, lines) => match lines {
+                l if l == DOWN | RIGHT => '┌',
+                l if l == DOWN | LEFT => '┐',
+                l if l == UP | RIGHT => '└',
+                l if l == UP | LEFT => '┘',
+                l if l == UP | DOWN | RIGHT => '├',
+                l if l == UP | DOWN | LEFT => '┤',
+                l if l == DOWN | LEFT | RIGHT => '┬',
+                l if l == UP | LEFT | RIGHT => '┴',
+                _ => '┼',
+            },
+        }
+    }
+}
+
+fn draw(
+    layers: &[Vec<Item>],
+    links: &[Link],
+    label: &impl Fn(NodeIndex) -> String,
+    ascii: bool,
+) -> String {
+    if layers.is_empty() {
+        return String::new();
+    }
+    let width_of = |item: &Item| match item {
+        Item::Task(idx) => label(*idx).chars().count() + 4,
+        Item::Dummy(_) => 1,
+    };
+    let row_width = |layer: &Vec<Item>| {
+        layer.iter().map(width_of).sum::<usize>() + GAP * layer.len().saturating_sub(1)
+    };
+    let width = layers.iter().map(row_width).max().unwrap_or(0);
+
+    // Where every item starts, centering each layer.
+    let mut x_of = HashMap::new();
+    for layer in layers {
+        let mut x = (width - row_width(layer)) / 2;
+        for item in layer {
+            x_of.insert(*item, x);
+            x += width_of(item) + GAP;
+        }
+    }
+    let center = |item: &Item| x_of[item] + width_of(item) / 2;
+
+    // Every item with links to the side gets its own row to spread them out,
+    // and one more row holds the arrowheads.
+    let tracks: Vec<Vec<Item>> = layers
+        .iter()
+        .map(|layer| {
+            layer
+                .iter()
+                .copied()
+                .filter(|item| {
+                    links
+                        .iter()
+                        .any(|link| link.from == *item && center(&link.to) != center(item))
+                })
+                .collect()
+        })
+        .collect();
+    let mut y_of = vec![0];
+    for track in &tracks {
+        let top = y_of.last().unwrap();
+        y_of.push(top + 3 + track.len().max(1) + 1);
+    }
+    let height = y_of[layers.len() - 1] + 3;
+
+    let mut canvas = Canvas::new(width, height);
+    for (layer, y) in layers.iter().zip(&y_of) {
+        for item in layer {
+            let x = x_of[item];
+            match item {
+                Item::Task(idx) => {
+                    let w = width_of(item) - 1;
+                    canvas.horizontal(*y, x, x + w, false);
+                    canvas.horizontal(y + 2, x, x + w, false);
+                    canvas.vertical(x, *y, y + 2, false);
+                    canvas.vertical(x + w, *y, y + 2, false);
+                    canvas.text(x + 2, y + 1, &label(*idx));
+                }
+                Item::Dummy(_) => {
+                    let dashed = links.iter().any(|link| link.to == *item && link.dashed);
+                    canvas.vertical(x, *y, y + 2, dashed);
+                }
+            }
+        }
+    }
+
+    for (i, track) in tracks.iter().enumerate().take(layers.len() - 1) {
+        let bottom = y_of[i] + 2;
+        let arrows = y_of[i + 1] - 1;
+        for source in &layers[i] {
+            let sx = center(source);
+            // Links straight down don't need a row of their own.
+            let y = match track.iter().position(|item| item == source) {
+                Some(row) => bottom + 1 + row,
+                None => bottom,
+            };
+            for link in links.iter().filter(|link| link.from == *source) {
+                let tx = center(&link.to);
+                // Edges going through the next layer continue into it.
+                let end = match link.to {
+                    Item::Task(_) => arrows,
+                    Item::Dummy(_) => arrows + 1,
+                };
+                canvas.vertical(sx, bottom, y, link.dashed);
+                canvas.horizontal(y, sx, tx, link.dashed);
+                canvas.vertical(tx, y, end, link.dashed);
+            }
+        }
+        for item in &layers[i + 1] {
+            let Item::Task(_) = item else { continue };
+            let choice = links.iter().any(|link| link.to == *item && link.choice);
+            let head = match (ascii, choice) {
+                (true, true) => 'o',
+                (true, false) => 'v',
+                (false, true) => '▽',
+                (false, false) => '▼',
+            };
+            canvas.cells[arrows][center(item)].text = Some(head);
+        }
+    }
+
+    canvas.render(ascii)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{Graph, Ir, Valid, ir};
+
+    fn render(input: &str, style: TextStyle) -> String {
+        let graph = Graph::<ir::Node, Ir, Valid>::new(ir::parse(input).unwrap().0);
+        render_text(&graph.to_petgraph_with_loops(), None, style)
+    }
+
+    #[test]
+    fn test_compact_has_a_line_per_layer() {
+        let style = TextStyle {
+            ascii: true,
+            compact: true,
+        };
+
+        assert_eq!(
+            render("$s0,{[s1,s2],s3},s4$", style),
+            "0 | s0\n1 | s1 s3\n2 | s2\n3 | s4\n"
+        );
+    }
+
+    #[test]
+    fn test_boxes_and_arrows() {
+        let style = TextStyle {
+            ascii: false,
+            compact: false,
+        };
+
+        assert_eq!(
+            render("$s0,{s1,s2}$", style),
+            "    ┌────┐\n    │ s0 │\n    └──┬─┘\n   ┌───┴───┐\n   ▼       ▼\n\
+             ┌────┐  ┌────┐\n│ s1 │  │ s2 │\n└────┘  └────┘\n"
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt;
+
+use petgraph::Direction;
+
+use crate::Error;
+use crate::graph::{Graph, Ir, Valid, ir};
+
+/// Rule used to pick the next task when several are ready and a processor is idle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Heuristic {
+    /// Highest Level First with Estimated Times: longest path to the end first.
+    Hlfet,
+    /// Least slack first, so the critical path is never delayed when it can be avoided.
+    CriticalPathFirst,
+    /// Tasks run in the order they became ready.
+    Fifo,
+}
+
+/// A task placed on a processor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Slot {
+    pub task: String,
+    pub processor: usize,
+    pub start: u64,
+    pub finish: u64,
+    pub critical: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub processors: usize,
+    pub heuristic: Heuristic,
+    /// Slots sorted by start time.
+    pub slots: Vec<Slot>,
+    pub makespan: u64,
+    /// Time needed to run every task on a single processor.
+    pub sequential: u64,
+}
+
+impl Schedule {
+    pub fn speedup(&self) -> f64 {
+        if self.makespan == 0 {
+            return 1.0;
+        }
+        self.sequential as f64 / self.makespan as f64
+    }
+
+    /// Fraction of the processor time spent running tasks.
+    pub fn efficiency(&self) -> f64 {
+        self.speedup() / self.processors as f64
+    }
+}
+
+struct Task {
+    name: String,
+    duration: u64,
+    preds: Vec<usize>,
+    /// Smaller runs first.
+    priority: (u64, u64),
+    critical: bool,
+}
+
+impl Graph<ir::Node, Ir, Valid> {
+    /// List scheduling of the tasks on `processors` identical workers: whenever a
+    /// processor is idle it takes the ready task `heuristic` ranks first.
+    ///
+    /// Only one alternative of every choice runs, so every way the choices can
+    /// go is scheduled on its own and the slowest one is kept.
+    pub fn schedule(&self, processors: usize, heuristic: Heuristic) -> Result<Schedule, Error> {
+        let mut slowest: Option<Schedule> = None;
+        for nodes in ir::resolutions(&self.0) {
+            let schedule = Graph::new(nodes).schedule_tasks(processors, heuristic)?;
+            if slowest
+                .as_ref()
+                .is_none_or(|slowest| schedule.makespan > slowest.makespan)
+            {
+                slowest = Some(schedule);
+            }
+        }
+        Ok(slowest.unwrap())
+    }
+
+    fn schedule_tasks(&self, processors: usize, heuristic: Heuristic) -> Result<Schedule, Error> {
+        let processors = processors.max(1);
+        let petgraph = self.to_petgraph();
+        let critical_path = self.critical_path()?;
+
+        let tasks: Vec<_> = petgraph
+            .node_indices()
+            .map(|idx| {
+                let name = petgraph[idx].clone();
+                let activity = critical_path
+                    .activities
+                    .iter()
+                    .find(|activity| activity.name == name)
+                    .unwrap();
+                // Ties are broken by declaration order.
+                let position = idx.index() as u64;
+                let mut preds: Vec<_> = petgraph
+                    .neighbors_directed(idx, Direction::Incoming)
+                    .map(|n| n.index())
+                    .collect();
+                preds.sort_unstable();
+                preds.dedup();
+
+                let priority = match heuristic {
+                    // The static level is `makespan - LS`, so the highest level
+                    // is the lowest latest start.
+                    Heuristic::Hlfet => (activity.latest_start, position),
+                    Heuristic::CriticalPathFirst => (activity.slack, activity.earliest_start),
+                    Heuristic::Fifo => (0, position),
+                };
+
+                Task {
+                    name,
+                    duration: activity.duration,
+                    preds,
+                    priority,
+                    critical: activity.is_critical(),
+                }
+            })
+            .collect();
+
+        // No processor idles while there is work left, so nothing finishes
+        // after the tasks would on a single processor.
+        let sequential = tasks
+            .iter()
+            .try_fold(0u64, |total, task| total.checked_add(task.duration))
+            .ok_or_else(|| {
+                Error::Overflow(format!("the tasks last longer than {} in total", u64::MAX))
+            })?;
+
+        let mut finish: Vec<Option<u64>> = vec![None; tasks.len()];
+        let mut started = vec![false; tasks.len()];
+        let mut ready_since: Vec<Option<u64>> = vec![None; tasks.len()];
+        let mut busy_until = vec![0u64; processors];
+        let mut slots = vec![];
+        let mut time = 0;
+
+        while slots.len() < tasks.len() {
+            for (n, task) in tasks.iter().enumerate() {
+                let done = task
+                    .preds
+                    .iter()
+                    .all(|&p| finish[p].is_some_and(|f| f <= time));
+                if !started[n] && ready_since[n].is_none() && done {
+                    ready_since[n] = Some(time);
+                }
+            }
+
+            for (processor, busy_until) in busy_until.iter_mut().enumerate() {
+                if *busy_until > time {
+                    continue;
+                }
+                let next = (0..tasks.len())
+                    .filter(|&n| !started[n] && ready_since[n].is_some())
+                    .min_by_key(|&n| match heuristic {
+                        Heuristic::Fifo => (ready_since[n].unwrap(), tasks[n].priority.1),
+                        _ => tasks[n].priority,
+                    });
+                let Some(n) = next else {
+                    break;
+                };
+
+                started[n] = true;
+                finish[n] = Some(time + tasks[n].duration);
+                *busy_until = time + tasks[n].duration;
+                slots.push(Slot {
+                    task: tasks[n].name.clone(),
+                    processor,
+                    start: time,
+                    finish: time + tasks[n].duration,
+                    critical: tasks[n].critical,
+                });
+            }
+
+            // Jump to the next moment a processor frees up.
+            let next_time = busy_until.iter().copied().filter(|&t| t > time).min();
+            match next_time {
+                Some(next) => time = next,
+                // Everything finished now: only zero-length tasks were placed.
+                None if slots.len() < tasks.len() => {}
+                None => break,
+            }
+        }
+
+        Ok(Schedule {
+            processors,
+            heuristic,
+            makespan: slots.iter().map(|slot| slot.finish).max().unwrap_or(0),
+            sequential,
+            slots,
+        })
+    }
+}
+
+impl TryFrom<&str> for Heuristic {
+    type Error = Error;
+
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        match name {
+            "hlfet" => Ok(Heuristic::Hlfet),
+            "cp" | "critical-path" => Ok(Heuristic::CriticalPathFirst),
+            "fifo" => Ok(Heuristic::Fifo),
+            _ => Err(Error::InvalidParams),
+        }
+    }
+}
+
+impl fmt::Display for Heuristic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Heuristic::Hlfet => write!(f, "HLFET"),
+            Heuristic::CriticalPathFirst => write!(f, "critical path first"),
+            Heuristic::Fifo => write!(f, "FIFO"),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<12} {:>4} {:>6} {:>6}", "task", "cpu", "start", "end")?;
+        for slot in &self.slots {
+            writeln!(
+                f,
+                "{:<12} {:>4} {:>6} {:>6}{}",
+                slot.task,
+                format!("P{}", slot.processor),
+                slot.start,
+                slot.finish,
+                if slot.critical { "  *" } else { "" }
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Heuristic: {}", self.heuristic)?;
+        writeln!(f, "Processors: {}", self.processors)?;
+        writeln!(f, "Makespan: {}", self.makespan)?;
+        write!(
+            f,
+            "Speed-up: {:.2} (efficiency {:.0}%)",
+            self.speedup(),
+            self.efficiency() * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(input: &str, processors: usize, heuristic: Heuristic) -> Schedule {
+        let graph = ir::parse(input).unwrap();
+        Graph::<ir::Node, Ir, Valid>::new(graph.0)
+            .schedule(processors, heuristic)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_single_processor_is_sequential() {
+        let result = schedule("$s0:2,{s1:3,s2:1},s3:1$", 1, Heuristic::Hlfet);
+
+        assert_eq!(result.makespan, 7);
+        assert_eq!(result.speedup(), 1.0);
+    }
+
+    #[test]
+    fn test_hlfet_starts_longest_chain_first() {
+        // FIFO picks s1 first and leaves the long chain for the second round.
+        let input = "$s0:1,{s1:1,s2:1,[s3:2,s4:2]}$";
+
+        assert_eq!(schedule(input, 1, Heuristic::Hlfet).slots[1].task, "s3");
+        assert_eq!(schedule(input, 1, Heuristic::Fifo).slots[1].task, "s1");
+        assert_eq!(schedule(input, 2, Heuristic::Hlfet).makespan, 5);
+    }
+
+    #[test]
+    fn test_alternatives_never_share_processors() {
+        let result = schedule("$s0:1,(s1:2|[s2:1,s3:3]),s4:1$", 2, Heuristic::Hlfet);
+
+        assert_eq!(result.makespan, 6);
+        assert!(result.slots.iter().all(|slot| slot.task != "s1"));
+    }
+}
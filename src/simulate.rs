@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::graph::{ForkJoin, Graph, fk};
+
+/// Upper bound on executed statements, so programs that loop forever still
+/// produce a report.
+const MAX_STEPS: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThreadState {
+    Running,
+    /// Waiting on a join counter that hasn't reached zero yet.
+    Blocked(String),
+    /// Merged into the last thread that arrived at the join.
+    Joined(String),
+    /// Ended through an explicit `quit`.
+    Quit,
+    /// Reached the `end` of the program.
+    Ended,
+    /// Disappeared without reaching a join, a `quit` or `end`.
+    Lost(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Thread {
+    pub id: usize,
+    pub pc: usize,
+    pub state: ThreadState,
+}
+
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub thread: usize,
+    pub pc: usize,
+    pub stmt: String,
+    /// Threads alive (running or blocked) after the step.
+    pub live: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub struct Simulation {
+    pub steps: Vec<Step>,
+    pub threads: Vec<Thread>,
+    /// Tasks in the order they were executed.
+    pub trace: Vec<String>,
+    pub notes: Vec<String>,
+}
+
+impl Simulation {
+    /// Threads blocked forever on a join.
+    pub fn deadlocked(&self) -> Vec<&Thread> {
+        self.threads
+            .iter()
+            .filter(|t| matches!(t.state, ThreadState::Blocked(_)))
+            .collect()
+    }
+
+    pub fn lost(&self) -> Vec<&Thread> {
+        self.threads
+            .iter()
+            .filter(|t| matches!(t.state, ThreadState::Lost(_)))
+            .collect()
+    }
+
+    /// Threads still running when the simulation gave up, so the program
+    /// never terminates.
+    pub fn running(&self) -> Vec<&Thread> {
+        self.threads
+            .iter()
+            .filter(|t| t.state == ThreadState::Running)
+            .collect()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.deadlocked().is_empty() && self.lost().is_empty() && self.running().is_empty()
+    }
+}
+
+impl<S> Graph<fk::Stmt, ForkJoin, S> {
+    /// Executes the program with a round-robin scheduler, one statement per step.
+    ///
+    /// `join c` follows Conway's semantics: it decrements the counter and only the
+    /// last arriving thread continues, the others wait for it and are merged.
+    pub fn simulate(&self) -> Simulation {
+        Interpreter::new(&self.0).run()
+    }
+}
+
+struct Interpreter<'a> {
+    stmts: &'a [fk::Stmt],
+    labels: HashMap<&'a str, usize>,
+    /// Arity inferred from the program, for counters that are never declared.
+    arities: HashMap<String, usize>,
+    counters: HashMap<String, i64>,
+    threads: Vec<Thread>,
+    steps: Vec<Step>,
+    trace: Vec<String>,
+    notes: Vec<String>,
+    /// Conditions already reported as taken false.
+    assumed: Vec<String>,
+    ended_by: Option<usize>,
+}
+
+impl<'a> Interpreter<'a> {
+    fn new(stmts: &'a [fk::Stmt]) -> Self {
+        let labels = stmts
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, stmt)| stmt.label.as_deref().map(|label| (label, idx)))
+            .collect();
+
+        Self {
+            stmts,
+            labels,
+            arities: fk::join_arities(stmts).into_iter().collect(),
+            counters: HashMap::new(),
+            threads: vec![Thread {
+                id: 0,
+                pc: 0,
+                state: ThreadState::Running,
+            }],
+            steps: vec![],
+            trace: vec![],
+            notes: vec![],
+            assumed: vec![],
+            ended_by: None,
+        }
+    }
+
+    fn run(mut self) -> Simulation {
+        let mut next = 0;
+
+        while let Some(thread) = self.next_running(next) {
+            if self.steps.len() == MAX_STEPS {
+                break;
+            }
+            self.step(thread);
+            next = thread + 1;
+        }
+
+        Simulation {
+            steps: self.steps,
+            threads: self.threads,
+            trace: self.trace,
+            notes: self.notes,
+        }
+    }
+
+    /// Round-robin: the first running thread starting from `from`.
+    fn next_running(&self, from: usize) -> Option<usize> {
+        let len = self.threads.len();
+        (0..len)
+            .map(|offset| (from + offset) % len)
+            .find(|&id| self.threads[id].state == ThreadState::Running)
+    }
+
+    fn step(&mut self, id: usize) {
+        let pc = self.threads[id].pc;
+        let Some(stmt) = self.stmts.get(pc) else {
+            self.threads[id].state = ThreadState::Lost("ran past the last statement".to_string());
+            return;
+        };
+
+        match &stmt.node {
+            fk::Node::Atomic { id: task, .. } if task == "end" => self.end(id),
+            fk::Node::Atomic { id: task, .. } => {
+                self.trace.push(task.clone());
+                self.threads[id].pc += 1;
+            }
+            fk::Node::Counter { id: counter, count } => {
+                self.counters.insert(counter.clone(), *count as i64);
+                self.threads[id].pc += 1;
+            }
+            fk::Node::Fork { id: label } => {
+                self.threads[id].pc += 1;
+                match self.labels.get(label.as_str()) {
+                    Some(&target) => self.threads.push(Thread {
+                        id: self.threads.len(),
+                        pc: target,
+                        state: ThreadState::Running,
+                    }),
+                    None => self
+                        .notes
+                        .push(format!("T{id}: fork to undefined label '{label}' ignored")),
+                }
+            }
+            fk::Node::Goto { id: label } => match self.labels.get(label.as_str()) {
+                Some(&target) => self.threads[id].pc = target,
+                None if label == "end" || label == "_end" => self.end(id),
+                None => {
+                    self.threads[id].state =
+                        ThreadState::Lost(format!("goto undefined label '{label}'"));
+                }
+            },
+            // Conditions are never evaluated: the first alternative always runs.
+            fk::Node::If { cond, .. } => {
+                if !self.assumed.contains(cond) {
+                    self.notes.push(format!("Condition '{cond}' assumed false"));
+                    self.assumed.push(cond.clone());
+                }
+                self.threads[id].pc += 1;
+            }
+            fk::Node::Join { id: counter } => self.join(id, counter),
+            fk::Node::Quit => self.threads[id].state = ThreadState::Quit,
+            fk::Node::Final => self.end(id),
+        }
+
+        let live = self
+            .threads
+            .iter()
+            .filter(|t| matches!(t.state, ThreadState::Running | ThreadState::Blocked(_)))
+            .map(|t| t.id)
+            .collect();
+        self.steps.push(Step {
+            thread: id,
+            pc,
+            stmt: stmt.node.to_string(),
+            live,
+        });
+    }
+
+    fn join(&mut self, id: usize, counter: &str) {
+        let remaining = match self.counters.get_mut(counter) {
+            Some(count) => count,
+            None => {
+                let arity = self.arities.get(counter).copied().unwrap_or(1);
+                self.notes.push(format!(
+                    "Counter '{counter}' used before initialization, assuming {arity}"
+                ));
+                self.counters
+                    .entry(counter.to_string())
+                    .or_insert(arity as i64)
+            }
+        };
+        *remaining -= 1;
+
+        if *remaining < 0 {
+            self.notes.push(format!(
+                "T{id}: join {counter} reached by more threads than its counter allows"
+            ));
+        }
+        if *remaining != 0 {
+            self.threads[id].state = ThreadState::Blocked(counter.to_string());
+            return;
+        }
+
+        for thread in &mut self.threads {
+            if thread.state == ThreadState::Blocked(counter.to_string()) {
+                thread.state = ThreadState::Joined(counter.to_string());
+            }
+        }
+        self.threads[id].pc += 1;
+    }
+
+    fn end(&mut self, id: usize) {
+        match self.ended_by {
+            Some(first) => {
+                self.threads[id].state =
+                    ThreadState::Lost(format!("reached end after T{first} already did"));
+            }
+            None => {
+                self.ended_by = Some(id);
+                self.threads[id].state = ThreadState::Ended;
+            }
+        }
+    }
+}
+
+impl fmt::Display for ThreadState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThreadState::Running => write!(f, "running"),
+            ThreadState::Blocked(counter) => write!(f, "blocked on {counter}"),
+            ThreadState::Joined(counter) => write!(f, "joined at {counter}"),
+            ThreadState::Quit => write!(f, "quit"),
+            ThreadState::Ended => write!(f, "reached end"),
+            ThreadState::Lost(reason) => write!(f, "lost: {reason}"),
+        }
+    }
+}
+
+impl fmt::Display for Simulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, step) in self.steps.iter().enumerate() {
+            let live = step
+                .live
+                .iter()
+                .map(|t| format!("T{t}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(
+                f,
+                "{:>4}  T{:<3} #{:<3} {:<16} live: {{{live}}}",
+                idx + 1,
+                step.thread,
+                step.pc,
+                step.stmt
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Trace: {}", self.trace.join(", "))?;
+        for thread in &self.threads {
+            writeln!(f, "T{}: {}", thread.id, thread.state)?;
+        }
+        for note in &self.notes {
+            writeln!(f, "Note: {note}")?;
+        }
+
+        let deadlocked = self.deadlocked();
+        if !deadlocked.is_empty() {
+            let threads = deadlocked
+                .iter()
+                .map(|t| format!("T{} ({})", t.id, t.state))
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(f, "Deadlock: {threads}")?;
+        }
+
+        let lost = self.lost();
+        if !lost.is_empty() {
+            let threads = lost
+                .iter()
+                .map(|t| format!("T{}", t.id))
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(f, "Lost threads: {threads}")?;
+        }
+
+        let running = self.running();
+        if !running.is_empty() {
+            let threads = running
+                .iter()
+                .map(|t| format!("T{}", t.id))
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(
+                f,
+                "Non-termination: {threads} still running after {MAX_STEPS} steps"
+            )?;
+        }
+
+        if self.is_ok() {
+            write!(f, "Program finished")
+        } else if !running.is_empty() {
+            write!(f, "Program stopped with errors")
+        } else {
+            write!(f, "Program finished with errors")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simulate(input: &str) -> Simulation {
+        let graph = fk::parse(input).unwrap();
+        Graph::<fk::Stmt, ForkJoin>::new(graph.0).simulate()
+    }
+
+    #[test]
+    fn test_fork_join_completes() {
+        let sim = simulate(
+            "begin\nc1 := 2\ns1\nfork L2\ns2\nL1: join c1\ns4\ngoto _end\nL2: s3\ngoto L1\n_end: end\nend",
+        );
+
+        assert!(sim.is_ok());
+        assert_eq!(sim.trace.len(), 4);
+        assert_eq!(sim.trace.last().unwrap(), "s4");
+    }
+
+    #[test]
+    fn test_join_never_completed_deadlocks() {
+        let sim = simulate(
+            "begin\nc1 := 3\nfork L2\ns1\nL1: join c1\ngoto _end\nL2: s2\ngoto L1\n_end: end\nend",
+        );
+
+        assert_eq!(sim.deadlocked().len(), 2);
+    }
+
+    #[test]
+    fn test_converted_loop_runs_once_and_leaves() {
+        let nodes = crate::graph::ir::parse("$s0,[s1,s2]*,s3$").unwrap().0;
+        let graph = fk::Graph::from_ir(&crate::graph::ir::Graph::new(nodes));
+        let sim = Graph::<fk::Stmt, ForkJoin>::new(graph.0).simulate();
+
+        assert!(sim.is_ok());
+        assert_eq!(sim.trace, ["s0", "s1", "s2", "s3"]);
+    }
+
+    #[test]
+    fn test_dangling_branch_runs_but_does_not_convert() {
+        // The forked thread runs past the last statement.
+        let input = "begin\nfork L1\ns1\ngoto _end\n_end: end\nL1: s2\nend";
+        let sim = crate::simulate(input).unwrap();
+
+        assert!(sim.trace.contains(&"s2".to_string()));
+        assert_eq!(sim.lost().len(), 1);
+        assert!(crate::parse(input, crate::Format::ForkJoin).is_err());
+    }
+
+    #[test]
+    fn test_endless_loop_does_not_terminate() {
+        let sim = simulate("begin\nR: s1\ngoto R\nend");
+
+        assert!(!sim.is_ok());
+        assert_eq!(sim.running().len(), 1);
+        assert!(sim.to_string().contains("Non-termination: T0"));
+    }
+
+    #[test]
+    fn test_goto_undefined_label_is_lost() {
+        let sim = crate::simulate("begin\ns1\ngoto L9\nend").unwrap();
+
+        assert_eq!(
+            sim.threads[0].state,
+            ThreadState::Lost("goto undefined label 'L9'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_fork_to_undefined_label_is_ignored() {
+        let sim = crate::simulate("begin\nfork L9\ns1\n_end: end\nend").unwrap();
+
+        assert!(sim.is_ok());
+        assert_eq!(sim.threads.len(), 1);
+        assert_eq!(sim.notes, ["T0: fork to undefined label 'L9' ignored"]);
+    }
+
+    #[test]
+    fn test_thread_falling_off_is_lost() {
+        let sim = simulate("begin\nfork L1\ns1\nquit\nL1: s2\nend");
+
+        assert_eq!(sim.lost().len(), 1);
+    }
+}
@@ -1,43 +1,111 @@
 use std::collections::{HashMap, HashSet};
 
-use crate::error::{ValidationError, ValidationErrorKind};
+use petgraph::algo::has_path_connecting;
+
+use crate::error::{Span, ValidationError, ValidationErrorKind};
 use crate::graph::{Graph, Ir, Unvalidated, Valid, ir};
 
 pub type ValidationResult<T = ()> = Result<T, Vec<ValidationError>>;
 
 impl Graph<ir::Node, Ir, Unvalidated> {
     pub fn validate(self) -> ValidationResult<Graph<ir::Node, Ir, Valid>> {
-        let mut errors = vec![];
+        self.validate_with_warnings().map(|(graph, _)| graph)
+    }
+
+    /// Like [`Graph::validate`], but also returns the warnings found on a
+    /// valid graph.
+    pub fn validate_with_warnings(
+        self,
+    ) -> ValidationResult<(Graph<ir::Node, Ir, Valid>, Vec<ValidationError>)> {
+        let mut diagnostics = vec![];
         let nodes = collect_all_nodes(&self.0);
+        let mut labels = vec![];
+        collect_labels(&self.0, &mut labels);
+
+        let mut tasks = vec![];
+        collect_task_spans(&self.0, &mut tasks);
+        if let Err(duplicates) = check_duplicate_tasks(tasks) {
+            diagnostics.extend(duplicates);
+        }
+
+        if let Err(duplicates) = check_duplicate_labels(&labels) {
+            diagnostics.extend(duplicates);
+        }
 
-        if let Err(missing) = check_missing_dependencies(&nodes) {
-            errors.extend(missing);
+        if let Err(missing) = check_missing_dependencies(&nodes, &labels) {
+            diagnostics.extend(missing);
         }
 
         if let Err(circular) = check_circular_dependencies(&nodes) {
-            errors.extend(circular);
+            diagnostics.extend(circular);
+        }
+
+        if diagnostics.is_empty()
+            && let Err(late) = check_dependency_order(&self.0, &nodes)
+        {
+            diagnostics.extend(late);
+        }
+
+        // Concurrency is only meaningful once the precedence graph is sound.
+        if diagnostics.is_empty()
+            && let Err(races) = check_bernstein_conditions(&self.0)
+        {
+            diagnostics.extend(races);
         }
 
-        if errors.is_empty() {
-            Ok(Graph::new(self.0))
-        } else {
-            Err(errors)
+        if diagnostics
+            .iter()
+            .any(|diagnostic| !diagnostic.is_warning())
+        {
+            return Err(diagnostics);
         }
+
+        let graph = Graph::new(self.0);
+        diagnostics.extend(graph.redundant_dependencies());
+        Ok((graph, diagnostics))
     }
 }
 
 impl Graph<ir::Node, Ir, Valid> {
     pub fn to_petgraph(&self) -> petgraph::Graph<String, &'static str> {
-        let mut graph = petgraph::Graph::new();
-        let mut node_indices = HashMap::new();
-
-        add_nodes_to_petgraph(&self.0, &mut graph, &mut node_indices, &[]);
-        add_edges_to_petgraph(&self.0, &mut graph, &node_indices);
+        build_petgraph(&self.0)
+    }
 
+    /// Like [`Graph::to_petgraph`], plus a `"loop"` edge from the end of every
+    /// loop body back to its start. The result may be cyclic.
+    pub fn to_petgraph_with_loops(&self) -> petgraph::Graph<String, &'static str> {
+        let mut graph = build_petgraph(&self.0);
+        let node_indices = graph
+            .node_indices()
+            .map(|idx| (graph[idx].clone(), idx))
+            .collect();
+        add_loop_edges(&self.0, &mut graph, &node_indices);
         graph
     }
 }
 
+fn build_petgraph(nodes: &[ir::Node]) -> petgraph::Graph<String, &'static str> {
+    let mut graph = nesting_petgraph(nodes);
+    let node_indices = graph
+        .node_indices()
+        .map(|idx| (graph[idx].clone(), idx))
+        .collect();
+
+    let mut blocks = HashMap::new();
+    collect_labelled(nodes, &mut blocks);
+
+    add_edges_to_petgraph(nodes, &mut graph, &node_indices, &blocks);
+
+    graph
+}
+
+/// The precedence given by the nesting alone, without the `#{}` dependencies.
+pub(crate) fn nesting_petgraph(nodes: &[ir::Node]) -> petgraph::Graph<String, &'static str> {
+    let mut graph = petgraph::Graph::new();
+    add_nodes_to_petgraph(nodes, &mut graph, &mut HashMap::new(), &[]);
+    graph
+}
+
 fn add_nodes_to_petgraph(
     nodes: &[ir::Node],
     graph: &mut petgraph::Graph<String, &'static str>,
@@ -48,7 +116,7 @@ fn add_nodes_to_petgraph(
 
     for node in nodes {
         match node {
-            ir::Node::Atomic(id, _deps, is_terminal) => {
+            ir::Node::Atomic(id, _deps, is_terminal, _) => {
                 let idx = graph.add_node(id.clone());
                 node_indices.insert(id.clone(), idx);
 
@@ -58,7 +126,10 @@ fn add_nodes_to_petgraph(
 
                 prev = if *is_terminal { vec![] } else { vec![idx] };
             }
-            ir::Node::Seq(inner_nodes) => {
+            // A single iteration of a loop is what orders its tasks.
+            ir::Node::Seq(inner_nodes)
+            | ir::Node::Loop(inner_nodes)
+            | ir::Node::Labelled(_, inner_nodes, _) => {
                 add_nodes_to_petgraph(inner_nodes, graph, node_indices, &prev);
                 prev = get_last_indices(inner_nodes, node_indices);
             }
@@ -70,6 +141,28 @@ fn add_nodes_to_petgraph(
                 }
                 prev = all_last;
             }
+            // Only one alternative runs, so the edges into them are choices
+            // rather than forks.
+            ir::Node::Choice(alternatives) => {
+                let mut all_last = vec![];
+                for alternative in alternatives {
+                    add_nodes_to_petgraph(
+                        std::slice::from_ref(alternative),
+                        graph,
+                        node_indices,
+                        &prev,
+                    );
+                    for first in get_first_index(alternative, node_indices) {
+                        for p in &prev {
+                            if let Some(edge) = graph.find_edge(*p, first) {
+                                graph[edge] = "choice";
+                            }
+                        }
+                    }
+                    all_last.extend(get_last_index(alternative, node_indices));
+                }
+                prev = all_last;
+            }
             ir::Node::Dep(_) => {}
         }
     }
@@ -90,15 +183,17 @@ fn get_last_index(
     node_indices: &HashMap<String, petgraph::graph::NodeIndex>,
 ) -> Vec<petgraph::graph::NodeIndex> {
     match node {
-        ir::Node::Atomic(id, _, is_terminal) => {
+        ir::Node::Atomic(id, _, is_terminal, _) => {
             if *is_terminal {
                 vec![]
             } else {
                 node_indices.get(id).copied().into_iter().collect()
             }
         }
-        ir::Node::Seq(nodes) => get_last_indices(nodes, node_indices),
-        ir::Node::Par(branches) => branches
+        ir::Node::Seq(nodes) | ir::Node::Loop(nodes) | ir::Node::Labelled(_, nodes, _) => {
+            get_last_indices(nodes, node_indices)
+        }
+        ir::Node::Par(branches) | ir::Node::Choice(branches) => branches
             .iter()
             .flat_map(|b| get_last_index(b, node_indices))
             .collect(),
@@ -106,42 +201,133 @@ fn get_last_index(
     }
 }
 
+fn get_first_index(
+    node: &ir::Node,
+    node_indices: &HashMap<String, petgraph::graph::NodeIndex>,
+) -> Vec<petgraph::graph::NodeIndex> {
+    match node {
+        ir::Node::Atomic(id, ..) => node_indices.get(id).copied().into_iter().collect(),
+        ir::Node::Seq(nodes) | ir::Node::Loop(nodes) | ir::Node::Labelled(_, nodes, _) => nodes
+            .first()
+            .map(|n| get_first_index(n, node_indices))
+            .unwrap_or_default(),
+        ir::Node::Par(branches) | ir::Node::Choice(branches) => branches
+            .iter()
+            .flat_map(|b| get_first_index(b, node_indices))
+            .collect(),
+        ir::Node::Dep(_) => vec![],
+    }
+}
+
+fn add_loop_edges(
+    nodes: &[ir::Node],
+    graph: &mut petgraph::Graph<String, &'static str>,
+    node_indices: &HashMap<String, petgraph::graph::NodeIndex>,
+) {
+    for node in nodes {
+        match node {
+            ir::Node::Loop(inner) => {
+                let first = get_first_index(node, node_indices);
+                for last in get_last_indices(inner, node_indices) {
+                    for &start in &first {
+                        graph.add_edge(last, start, "loop");
+                    }
+                }
+                add_loop_edges(inner, graph, node_indices);
+            }
+            ir::Node::Seq(inner)
+            | ir::Node::Par(inner)
+            | ir::Node::Choice(inner)
+            | ir::Node::Labelled(_, inner, _) => {
+                add_loop_edges(inner, graph, node_indices);
+            }
+            ir::Node::Atomic(..) | ir::Node::Dep(_) => {}
+        }
+    }
+}
+
 fn add_edges_to_petgraph(
     nodes: &[ir::Node],
     graph: &mut petgraph::Graph<String, &'static str>,
     node_indices: &HashMap<String, petgraph::graph::NodeIndex>,
+    blocks: &Blocks<'_>,
 ) {
     for node in nodes {
         match node {
-            ir::Node::Atomic(id, deps, _) => {
+            ir::Node::Atomic(id, deps, ..) => {
                 if let Some(target_idx) = node_indices.get(id) {
                     for dep in deps {
-                        if let ir::Node::Dep(dep_id) = dep
-                            && let Some(source_idx) = node_indices.get(dep_id)
-                        {
-                            graph.add_edge(*source_idx, *target_idx, "dep");
+                        let ir::Node::Dep(dep_id) = dep else {
+                            continue;
+                        };
+                        for source_idx in dependency_indices(dep_id, blocks, node_indices) {
+                            graph.add_edge(source_idx, *target_idx, "dep");
                         }
                     }
                 }
             }
-            ir::Node::Seq(inner) | ir::Node::Par(inner) => {
-                add_edges_to_petgraph(inner, graph, node_indices);
+            ir::Node::Seq(inner)
+            | ir::Node::Par(inner)
+            | ir::Node::Loop(inner)
+            | ir::Node::Choice(inner)
+            | ir::Node::Labelled(_, inner, _) => {
+                add_edges_to_petgraph(inner, graph, node_indices, blocks);
             }
             ir::Node::Dep(_) => {}
         }
     }
 }
 
-fn collect_all_nodes(nodes: &[ir::Node]) -> HashMap<String, (Vec<String>, bool)> {
+/// Blocks labelled with `@label`, by label.
+type Blocks<'a> = HashMap<&'a str, &'a ir::Node>;
+
+fn collect_labelled<'a>(nodes: &'a [ir::Node], blocks: &mut Blocks<'a>) {
+    for node in nodes {
+        match node {
+            ir::Node::Labelled(label, inner, _) => {
+                blocks.insert(label, node);
+                collect_labelled(inner, blocks);
+            }
+            ir::Node::Seq(inner)
+            | ir::Node::Par(inner)
+            | ir::Node::Loop(inner)
+            | ir::Node::Choice(inner) => {
+                collect_labelled(inner, blocks);
+            }
+            ir::Node::Atomic(..) | ir::Node::Dep(_) => {}
+        }
+    }
+}
+
+/// Nodes a `#{dep}` waits for: the task itself, or the last ones of the block
+/// labelled `@dep`.
+fn dependency_indices(
+    dep: &str,
+    blocks: &Blocks<'_>,
+    node_indices: &HashMap<String, petgraph::graph::NodeIndex>,
+) -> Vec<petgraph::graph::NodeIndex> {
+    match dep.strip_prefix('@') {
+        Some(label) => blocks
+            .get(label)
+            .map(|block| get_last_index(block, node_indices))
+            .unwrap_or_default(),
+        None => node_indices.get(dep).copied().into_iter().collect(),
+    }
+}
+
+/// Every task with its dependencies, whether it's terminal and its attributes.
+type TaskMap<'a> = HashMap<String, (Vec<String>, bool, &'a ir::Attrs)>;
+
+fn collect_all_nodes(nodes: &[ir::Node]) -> TaskMap<'_> {
     let mut result = HashMap::new();
     collect_recursive(nodes, &mut result);
     result
 }
 
-fn collect_recursive(nodes: &[ir::Node], map: &mut HashMap<String, (Vec<String>, bool)>) {
+fn collect_recursive<'a>(nodes: &'a [ir::Node], map: &mut TaskMap<'a>) {
     for node in nodes {
         match node {
-            ir::Node::Atomic(id, deps, is_terminal) => {
+            ir::Node::Atomic(id, deps, is_terminal, attrs) => {
                 let dep_ids = deps
                     .iter()
                     .filter_map(|n| match n {
@@ -149,27 +335,139 @@ fn collect_recursive(nodes: &[ir::Node], map: &mut HashMap<String, (Vec<String>,
                         _ => None,
                     })
                     .collect();
-                map.insert(id.clone(), (dep_ids, *is_terminal));
+                map.insert(id.clone(), (dep_ids, *is_terminal, attrs));
+            }
+            ir::Node::Seq(inner)
+            | ir::Node::Par(inner)
+            | ir::Node::Loop(inner)
+            | ir::Node::Choice(inner)
+            | ir::Node::Labelled(_, inner, _) => {
+                collect_recursive(inner, map);
+            }
+            ir::Node::Dep(_) => {}
+        }
+    }
+}
+
+fn collect_task_spans<'a>(nodes: &'a [ir::Node], tasks: &mut Vec<(&'a str, Option<&'a Span>)>) {
+    for node in nodes {
+        match node {
+            ir::Node::Atomic(id, _, _, attrs) => tasks.push((id, attrs.span.as_ref())),
+            ir::Node::Seq(inner)
+            | ir::Node::Par(inner)
+            | ir::Node::Loop(inner)
+            | ir::Node::Choice(inner)
+            | ir::Node::Labelled(_, inner, _) => {
+                collect_task_spans(inner, tasks);
             }
-            ir::Node::Seq(inner) | ir::Node::Par(inner) => collect_recursive(inner, map),
             ir::Node::Dep(_) => {}
         }
     }
 }
 
+/// Reports every task identifier used more than once, pointing at all of its
+/// occurrences. Takes the tasks in source order.
+pub(crate) fn check_duplicate_tasks<'a>(
+    tasks: impl IntoIterator<Item = (&'a str, Option<&'a Span>)>,
+) -> Result<(), Vec<ValidationError>> {
+    check_duplicates(tasks, ValidationErrorKind::DuplicateTask, |id, count| {
+        format!("Task '{id}' is defined {count} times")
+    })
+}
+
+fn collect_labels<'a>(nodes: &'a [ir::Node], labels: &mut Vec<(&'a str, Option<&'a Span>)>) {
+    for node in nodes {
+        match node {
+            ir::Node::Labelled(label, inner, span) => {
+                labels.push((label, span.as_ref()));
+                collect_labels(inner, labels);
+            }
+            ir::Node::Seq(inner)
+            | ir::Node::Par(inner)
+            | ir::Node::Loop(inner)
+            | ir::Node::Choice(inner) => {
+                collect_labels(inner, labels);
+            }
+            ir::Node::Atomic(..) | ir::Node::Dep(_) => {}
+        }
+    }
+}
+
+/// Reports every label given to more than one block, since `#{@label}`
+/// couldn't tell which one it waits for.
+fn check_duplicate_labels(labels: &[(&str, Option<&Span>)]) -> Result<(), Vec<ValidationError>> {
+    check_duplicates(
+        labels.iter().copied(),
+        ValidationErrorKind::DuplicateLabel,
+        |label, count| format!("Label '@{label}' is given to {count} blocks"),
+    )
+}
+
+/// Reports every name that appears more than once, with a label on each of
+/// its occurrences.
+fn check_duplicates<'a>(
+    names: impl IntoIterator<Item = (&'a str, Option<&'a Span>)>,
+    kind: ValidationErrorKind,
+    message: impl Fn(&str, usize) -> String,
+) -> Result<(), Vec<ValidationError>> {
+    let mut occurrences: Vec<(&str, Vec<Option<&Span>>)> = vec![];
+    for (name, span) in names {
+        match occurrences.iter_mut().find(|(seen, _)| *seen == name) {
+            Some((_, spans)) => spans.push(span),
+            None => occurrences.push((name, vec![span])),
+        }
+    }
+
+    let errors: Vec<_> = occurrences
+        .into_iter()
+        .filter(|(_, spans)| spans.len() > 1)
+        .map(|(name, spans)| {
+            let error = ValidationError::new(kind, message(name, spans.len()));
+            spans
+                .into_iter()
+                .enumerate()
+                .fold(error, |error, (i, span)| {
+                    let label = if i == 0 {
+                        "first defined here"
+                    } else {
+                        "defined again here"
+                    };
+                    error.with_label(span.cloned(), label)
+                })
+        })
+        .collect();
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
 fn check_missing_dependencies(
-    nodes: &HashMap<String, (Vec<String>, bool)>,
+    nodes: &TaskMap<'_>,
+    labels: &[(&str, Option<&Span>)],
 ) -> Result<(), Vec<ValidationError>> {
     let mut errors = vec![];
     let all_ids: HashSet<_> = nodes.keys().cloned().collect();
 
-    for (node_id, (deps, _)) in nodes {
-        for dep_id in deps {
-            if !all_ids.contains(dep_id) {
-                errors.push(ValidationError::new(
-                    ValidationErrorKind::MissingDependency,
-                    format!("Node '{node_id}' depends on '{dep_id}' which doesn't exist"),
-                ));
+    for (node_id, (deps, _, attrs)) in nodes {
+        for (i, dep_id) in deps.iter().enumerate() {
+            let (exists, what) = match dep_id.strip_prefix('@') {
+                Some(label) => (
+                    labels.iter().any(|(defined, _)| *defined == label),
+                    "undefined block",
+                ),
+                None => (all_ids.contains(dep_id), "undefined task"),
+            };
+            if !exists {
+                errors.push(
+                    ValidationError::new(
+                        ValidationErrorKind::MissingDependency,
+                        format!("Node '{node_id}' depends on '{dep_id}' which doesn't exist"),
+                    )
+                    .with_label(attrs.dep_spans.get(i).cloned(), what),
+                );
             }
         }
     }
@@ -181,9 +479,7 @@ fn check_missing_dependencies(
     }
 }
 
-fn check_circular_dependencies(
-    nodes: &HashMap<String, (Vec<String>, bool)>,
-) -> Result<(), Vec<ValidationError>> {
+fn check_circular_dependencies(nodes: &TaskMap<'_>) -> Result<(), Vec<ValidationError>> {
     let mut errors = vec![];
     let mut visited = HashSet::new();
     let mut rec_stack = HashSet::new();
@@ -193,10 +489,16 @@ fn check_circular_dependencies(
             && let Some(cycle) =
                 detect_cycle(node_id, nodes, &mut visited, &mut rec_stack, &mut vec![])
         {
-            errors.push(ValidationError::new(
+            let error = ValidationError::new(
                 ValidationErrorKind::CircularDependency,
                 format!("Circular dependency: {}", cycle.join(" -> ")),
-            ));
+            );
+            // The first task closes the cycle, so it appears twice.
+            let error = cycle[1..].iter().fold(error, |error, id| {
+                let span = nodes.get(id).and_then(|(_, _, attrs)| attrs.span.clone());
+                error.with_label(span, "part of the cycle")
+            });
+            errors.push(error);
         }
     }
 
@@ -209,7 +511,7 @@ fn check_circular_dependencies(
 
 fn detect_cycle(
     node_id: &str,
-    nodes: &HashMap<String, (Vec<String>, bool)>,
+    nodes: &TaskMap<'_>,
     visited: &mut HashSet<String>,
     rec_stack: &mut HashSet<String>,
     path: &mut Vec<String>,
@@ -218,7 +520,7 @@ fn detect_cycle(
     rec_stack.insert(node_id.to_string());
     path.push(node_id.to_string());
 
-    if let Some((deps, _)) = nodes.get(node_id) {
+    if let Some((deps, ..)) = nodes.get(node_id) {
         for dep_id in deps {
             if !visited.contains(dep_id) {
                 if let Some(cycle) = detect_cycle(dep_id, nodes, visited, rec_stack, path) {
@@ -246,16 +548,187 @@ fn detect_cycle(
     None
 }
 
+/// Reports the dependencies on tasks that only run after the dependent one,
+/// which would wait forever.
+fn check_dependency_order(
+    nodes: &[ir::Node],
+    tasks: &TaskMap<'_>,
+) -> Result<(), Vec<ValidationError>> {
+    let graph = build_petgraph(nodes);
+    let node_indices: HashMap<_, _> = graph
+        .node_indices()
+        .map(|idx| (graph[idx].clone(), idx))
+        .collect();
+    let mut blocks = HashMap::new();
+    collect_labelled(nodes, &mut blocks);
+    let mut errors = vec![];
+
+    for (task, (deps, _, attrs)) in tasks {
+        let Some(&task_idx) = node_indices.get(task) else {
+            continue;
+        };
+        for (i, dep) in deps.iter().enumerate() {
+            let runs_later = dependency_indices(dep, &blocks, &node_indices)
+                .into_iter()
+                .any(|dep_idx| has_path_connecting(&graph, task_idx, dep_idx, None));
+            if runs_later {
+                errors.push(
+                    ValidationError::new(
+                        ValidationErrorKind::CircularDependency,
+                        format!("Node '{task}' depends on '{dep}' which only runs after it"),
+                    )
+                    .with_label(attrs.dep_spans.get(i).cloned(), "runs later"),
+                );
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Reports every pair of tasks that may run concurrently while one of them
+/// writes a variable the other reads or writes. Tasks in different
+/// alternatives of a choice never run together.
+fn check_bernstein_conditions(nodes: &[ir::Node]) -> Result<(), Vec<ValidationError>> {
+    let graph = build_petgraph(nodes);
+    let mut accesses = HashMap::new();
+    collect_accesses(nodes, &mut accesses);
+    let mut alternatives = HashMap::new();
+    collect_alternatives(nodes, &mut vec![], &mut 0, &mut alternatives);
+    let exclusive = |a: &String, b: &String| {
+        let (Some(a), Some(b)) = (alternatives.get(a), alternatives.get(b)) else {
+            return false;
+        };
+        a.iter().any(|(choice, alternative)| {
+            b.iter()
+                .any(|(other, taken)| choice == other && alternative != taken)
+        })
+    };
+
+    let reachable: Vec<HashSet<_>> = graph
+        .node_indices()
+        .map(|idx| {
+            let mut dfs = petgraph::visit::Dfs::new(&graph, idx);
+            let mut seen = HashSet::new();
+            while let Some(next) = dfs.next(&graph) {
+                seen.insert(next);
+            }
+            seen
+        })
+        .collect();
+
+    let tasks: Vec<_> = graph
+        .node_indices()
+        .filter_map(|idx| accesses.get(&graph[idx]).map(|attrs| (idx, *attrs)))
+        .collect();
+
+    let mut errors = vec![];
+    for (i, (a, a_attrs)) in tasks.iter().enumerate() {
+        for (b, b_attrs) in &tasks[i + 1..] {
+            if reachable[a.index()].contains(b)
+                || reachable[b.index()].contains(a)
+                || exclusive(&graph[*a], &graph[*b])
+            {
+                continue;
+            }
+
+            let conflicts = a_attrs.conflicts_with(b_attrs);
+            if conflicts.is_empty() {
+                continue;
+            }
+
+            let vars = conflicts
+                .iter()
+                .map(|var| format!("'{var}'"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            errors.push(
+                ValidationError::new(
+                    ValidationErrorKind::BernsteinViolation,
+                    format!(
+                        "Tasks '{}' and '{}' may run concurrently but conflict on {vars}",
+                        graph[*a], graph[*b],
+                    ),
+                )
+                .with_label(a_attrs.span.clone(), format!("conflicts on {vars}"))
+                .with_label(b_attrs.span.clone(), format!("with '{}'", graph[*a])),
+            );
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn collect_accesses<'a>(nodes: &'a [ir::Node], map: &mut HashMap<String, &'a ir::Attrs>) {
+    for node in nodes {
+        match node {
+            ir::Node::Atomic(id, _, _, attrs) if attrs.has_access() => {
+                map.insert(id.clone(), attrs);
+            }
+            ir::Node::Seq(inner)
+            | ir::Node::Par(inner)
+            | ir::Node::Loop(inner)
+            | ir::Node::Choice(inner)
+            | ir::Node::Labelled(_, inner, _) => {
+                collect_accesses(inner, map);
+            }
+            ir::Node::Atomic(..) | ir::Node::Dep(_) => {}
+        }
+    }
+}
+
+/// The alternative every task is in, for each of the choices around it,
+/// numbering the choices in source order.
+fn collect_alternatives(
+    nodes: &[ir::Node],
+    path: &mut Vec<(usize, usize)>,
+    choices: &mut usize,
+    map: &mut HashMap<String, Vec<(usize, usize)>>,
+) {
+    for node in nodes {
+        match node {
+            ir::Node::Atomic(id, ..) if !path.is_empty() => {
+                map.insert(id.clone(), path.clone());
+            }
+            ir::Node::Choice(alternatives) => {
+                let choice = *choices;
+                *choices += 1;
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    path.push((choice, i));
+                    collect_alternatives(std::slice::from_ref(alternative), path, choices, map);
+                    path.pop();
+                }
+            }
+            ir::Node::Seq(inner)
+            | ir::Node::Par(inner)
+            | ir::Node::Loop(inner)
+            | ir::Node::Labelled(_, inner, _) => {
+                collect_alternatives(inner, path, choices, map);
+            }
+            ir::Node::Atomic(..) | ir::Node::Dep(_) => {}
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_missing_dependency() {
+        let attrs = ir::Attrs::default();
         let mut nodes = HashMap::new();
-        nodes.insert("s0".to_string(), (vec!["s1".to_string()], false));
+        nodes.insert("s0".to_string(), (vec!["s1".to_string()], false, &attrs));
 
-        let result = check_missing_dependencies(&nodes);
+        let result = check_missing_dependencies(&nodes, &[]);
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err()[0].kind,
@@ -265,10 +738,11 @@ mod tests {
 
     #[test]
     fn test_circular_dependency() {
+        let attrs = ir::Attrs::default();
         let mut nodes = HashMap::new();
-        nodes.insert("s0".to_string(), (vec!["s1".to_string()], false));
-        nodes.insert("s1".to_string(), (vec!["s2".to_string()], false));
-        nodes.insert("s2".to_string(), (vec!["s0".to_string()], false));
+        nodes.insert("s0".to_string(), (vec!["s1".to_string()], false, &attrs));
+        nodes.insert("s1".to_string(), (vec!["s2".to_string()], false, &attrs));
+        nodes.insert("s2".to_string(), (vec!["s0".to_string()], false, &attrs));
 
         let result = check_circular_dependencies(&nodes);
         assert!(result.is_err());
@@ -278,18 +752,129 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_missing_dependency_points_at_source() {
+        let input = "$s0,s1#{s0,s9}$";
+        let graph = Graph::<ir::Node, Ir, Unvalidated>::parse(input).unwrap();
+        let errors = graph.validate().unwrap_err();
+
+        let (span, _) = &errors[0].labels[0];
+        assert_eq!(&input[span.clone()], "s9");
+    }
+
+    #[test]
+    fn test_dependency_on_later_task() {
+        let input = "$s0,s1#{s2},s2$";
+        let graph = Graph::<ir::Node, Ir, Unvalidated>::parse(input).unwrap();
+        let errors = graph.validate().unwrap_err();
+
+        assert_eq!(errors[0].kind, ValidationErrorKind::CircularDependency);
+        let (span, _) = &errors[0].labels[0];
+        assert_eq!(&input[span.clone()], "s2");
+    }
+
+    #[test]
+    fn test_duplicate_task() {
+        let input = "$s0,{s1,s1},s2$";
+        let graph = Graph::<ir::Node, Ir, Unvalidated>::parse(input).unwrap();
+        let errors = graph.validate().unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ValidationErrorKind::DuplicateTask);
+        let starts: Vec<_> = errors[0]
+            .labels
+            .iter()
+            .map(|(span, _)| span.start)
+            .collect();
+        assert_eq!(starts, vec![5, 8]);
+    }
+
+    #[test]
+    fn test_duplicate_label() {
+        let input = "$@a{s0,s1},@a[s2,s3],s4#{@a}$";
+        let graph = Graph::<ir::Node, Ir, Unvalidated>::parse(input).unwrap();
+        let errors = graph.validate().unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ValidationErrorKind::DuplicateLabel);
+        let spans: Vec<_> = errors[0]
+            .labels
+            .iter()
+            .map(|(span, _)| &input[span.clone()])
+            .collect();
+        assert_eq!(spans, vec!["@a", "@a"]);
+    }
+
+    #[test]
+    fn test_warnings_keep_graph_valid() {
+        let graph = Graph::<ir::Node, Ir, Unvalidated>::parse("$s0,[s1,s2#{s1}]$").unwrap();
+        let (_, warnings) = graph.validate_with_warnings().unwrap();
+
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].is_warning());
+    }
+
     #[test]
     fn test_valid_graph() {
         let result = Graph::<ir::Node, Ir, Unvalidated>::new(vec![
-            ir::Node::Atomic("s0".to_string(), vec![], false),
+            ir::Node::Atomic("s0".to_string(), vec![], false, ir::Attrs::default()),
             ir::Node::Atomic(
                 "s1".to_string(),
                 vec![ir::Node::Dep("s0".to_string())],
                 false,
+                ir::Attrs::default(),
             ),
         ])
         .validate();
 
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_bernstein_violation() {
+        let attrs = |reads: &[&str], writes: &[&str]| ir::Attrs {
+            reads: reads.iter().map(|v| v.to_string()).collect(),
+            writes: writes.iter().map(|v| v.to_string()).collect(),
+            ..ir::Attrs::default()
+        };
+        let nodes = vec![ir::Node::Par(vec![
+            ir::Node::Atomic("s1".to_string(), vec![], false, attrs(&["a"], &["b"])),
+            ir::Node::Atomic("s2".to_string(), vec![], false, attrs(&["b"], &["c"])),
+            ir::Node::Atomic("s3".to_string(), vec![], false, attrs(&["a"], &["d"])),
+        ])];
+
+        let result = check_bernstein_conditions(&nodes);
+        let errors = result.unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ValidationErrorKind::BernsteinViolation);
+    }
+
+    #[test]
+    fn test_alternatives_are_exclusive() {
+        let graph =
+            Graph::<ir::Node, Ir, Unvalidated>::parse("$s0,(s1(w:a)|[s2,s3(w:a)]),s4$").unwrap();
+        let graph = graph.validate().unwrap();
+        let petgraph = graph.to_petgraph();
+
+        let choices = petgraph
+            .edge_weights()
+            .filter(|weight| **weight == "choice")
+            .count();
+        assert_eq!(choices, 2);
+    }
+
+    #[test]
+    fn test_dependency_on_labelled_block() {
+        let graph =
+            Graph::<ir::Node, Ir, Unvalidated>::parse("$s0,{@f{[a,b],c},d},e#{@f}$").unwrap();
+        let petgraph = graph.validate().unwrap().to_petgraph();
+
+        let mut sources: Vec<_> = petgraph
+            .edge_indices()
+            .filter(|&edge| petgraph[edge] == "dep")
+            .map(|edge| petgraph[petgraph.edge_endpoints(edge).unwrap().0].as_str())
+            .collect();
+        sources.sort();
+        assert_eq!(sources, vec!["b", "c"]);
+    }
 }